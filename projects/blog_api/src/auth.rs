@@ -0,0 +1,449 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use axum::extract::{ConnectInfo, FromRequestParts, State};
+use axum::http::request::Parts;
+use axum::http::{header, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::Json;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::{ApiError, ApiJson, ApiPath, AppState, LockoutPolicy, Lockouts, LoginResult, Permission, RateLimitKey, Role, Users};
+
+// The only kind of token we make, and so the only kind we accept
+const TOKEN_HEADER: &str = r#"{"alg":"HS256","typ":"JWT"}"#;
+
+/// Logs users in, and checks the bearer tokens they're given.
+pub struct Auth {
+    users: Users,
+    lockouts: Lockouts,
+    secret: Vec<u8>,
+    token_lifetime: Duration,
+}
+
+impl Auth {
+    /// Tokens are signed with `secret`. Without one, a random secret is made
+    /// up, so tokens stop working when the server restarts. Failed logins
+    /// lock usernames and addresses out according to `lockout`.
+    pub fn new(users: Users, secret: Option<&str>, token_lifetime: Duration, lockout: LockoutPolicy) -> Self {
+        let secret = match secret {
+            Some(secret) => secret.as_bytes().to_vec(),
+            None => {
+                tracing::warn!("There's no token secret, so everyone will have to log in again after a restart");
+                let mut secret = uuid::Uuid::new_v4().into_bytes().to_vec();
+                secret.extend_from_slice(uuid::Uuid::new_v4().as_bytes());
+                secret
+            }
+        };
+        Self {
+            users,
+            lockouts: Lockouts::new(lockout),
+            secret,
+            token_lifetime,
+        }
+    }
+
+    /// Check a login, counting failures towards lockouts. While a username
+    /// or address is locked out, its passwords aren't checked at all, so
+    /// guessing gets nowhere. Hashing is slow, so call this from a blocking
+    /// task.
+    pub fn log_in(&self, username: &str, password: &str, ip: IpAddr) -> LoginResult {
+        if let Some(until) = self.lockouts.locked_until(username, ip) {
+            return LoginResult::LockedOut { until };
+        }
+        let result = self.users.is_login_valid(username, password);
+        match result {
+            LoginResult::Success { .. } => self.lockouts.record_success(username),
+            LoginResult::Failure => {
+                if let Some(until) = self.lockouts.record_failure(username, ip) {
+                    return LoginResult::LockedOut { until };
+                }
+            }
+            LoginResult::Denied | LoginResult::LockedOut { .. } => {}
+        }
+        result
+    }
+
+    // When a token issued at `now` expires. `Config::validate` keeps the
+    // lifetime short enough that this works, but if it's been set too long
+    // some other way, that's the server's fault, and a token that never
+    // expires is worse than no token.
+    fn expires_at(&self, now: DateTime<Utc>) -> Result<DateTime<Utc>, ApiError> {
+        now.checked_add_signed(self.token_lifetime)
+            .ok_or_else(|| ApiError::Internal(format!("The token lifetime ({}) is too long", self.token_lifetime)))
+    }
+
+    fn mac(&self) -> Hmac<Sha256> {
+        Hmac::new_from_slice(&self.secret).expect("HMAC takes keys of any length")
+    }
+
+    // A signed JSON Web Token for a user
+    fn issue(&self, claims: &Claims) -> String {
+        let payload = serde_json::to_vec(claims).expect("Claims always serialize");
+        let signed = format!("{}.{}", URL_SAFE_NO_PAD.encode(TOKEN_HEADER), URL_SAFE_NO_PAD.encode(payload));
+        let mut mac = self.mac();
+        mac.update(signed.as_bytes());
+        let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+        format!("{signed}.{signature}")
+    }
+
+    // The claims in a token, if we signed it and it hasn't expired
+    fn verify(&self, token: &str) -> Result<Claims, TokenRejected> {
+        let (signed, signature) = token.rsplit_once('.').ok_or(TokenRejected::Invalid)?;
+        let (header, payload) = signed.split_once('.').ok_or(TokenRejected::Invalid)?;
+
+        // The header is checked exactly, so a token can't pick a weaker
+        // algorithm (or none) for itself
+        if URL_SAFE_NO_PAD.decode(header).ok().as_deref() != Some(TOKEN_HEADER.as_bytes()) {
+            return Err(TokenRejected::Invalid);
+        }
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| TokenRejected::Invalid)?;
+        let mut mac = self.mac();
+        mac.update(signed.as_bytes());
+        mac.verify_slice(&signature).map_err(|_| TokenRejected::Invalid)?;
+
+        let payload = URL_SAFE_NO_PAD.decode(payload).map_err(|_| TokenRejected::Invalid)?;
+        let claims: Claims = serde_json::from_slice(&payload).map_err(|_| TokenRejected::Invalid)?;
+        if claims.exp <= Utc::now().timestamp() {
+            return Err(TokenRejected::Expired);
+        }
+        Ok(claims)
+    }
+}
+
+// What a token says about its holder. Their role isn't part of it, but
+// looked up on each request, so a change to it applies straight away.
+#[derive(Serialize, Deserialize)]
+struct Claims {
+    sub: String,
+    iat: i64,
+    exp: i64,
+}
+
+// Why a bearer token wasn't accepted, noted by `authenticate` for `AuthUser`
+#[derive(Clone, Copy)]
+enum TokenRejected {
+    Invalid,
+    Expired,
+}
+
+/// The logged-in user making a request. Handlers that take one turn away
+/// anyone without a valid bearer token with a 401, and denied users with a
+/// 403.
+#[derive(Clone, Debug)]
+pub struct AuthUser {
+    pub username: String,
+    pub role: Role,
+}
+
+impl AuthUser {
+    /// Refuse with a 403 unless the user's role allows `permission`, for
+    /// something written by `owner` if it's about an existing post or
+    /// comment.
+    pub fn require(&self, permission: Permission, owner: Option<&str>) -> Result<(), ApiError> {
+        let is_owner = owner == Some(self.username.as_str());
+        if self.role.allows(permission, is_owner) {
+            return Ok(());
+        }
+        tracing::info!(username = self.username, role = ?self.role, ?permission, "Refused permission");
+        Err(ApiError::Forbidden(format!("You don't have permission to {}", permission.describe())))
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AuthUser {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(user) = parts.extensions.get::<AuthUser>() {
+            if user.role == Role::Denied {
+                return Err(ApiError::Forbidden("Your account has been disabled".to_string()));
+            }
+            return Ok(user.clone());
+        }
+        let message = match parts.extensions.get::<TokenRejected>() {
+            Some(TokenRejected::Expired) => "Your token has expired; log in again",
+            Some(TokenRejected::Invalid) => "Your token isn't valid; log in again",
+            None => "Log in with POST /auth/login, and send the token as `Authorization: Bearer <token>`",
+        };
+        Err(ApiError::Unauthorized(message.to_string()))
+    }
+}
+
+// Middleware that checks the request's bearer token, if it has one, and
+// notes who sent it for `AuthUser` and the rate limiter. Requests without a
+// valid token carry on anonymously; it's up to each handler to refuse them.
+pub(crate) async fn authenticate<B>(State(auth): State<Arc<Auth>>, mut request: Request<B>, next: Next<B>) -> Response {
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if let Some(token) = token {
+        // A token for a user who's since been removed is no good either
+        let verified = auth.verify(token.trim()).and_then(|claims| {
+            let role = auth.users.role(&claims.sub).ok_or(TokenRejected::Invalid)?;
+            Ok((claims, role))
+        });
+        match verified {
+            Ok((claims, role)) => {
+                let extensions = request.extensions_mut();
+                extensions.insert(RateLimitKey(format!("user:{}", claims.sub)));
+                extensions.insert(AuthUser {
+                    username: claims.sub,
+                    role,
+                });
+            }
+            Err(rejected) => {
+                request.extensions_mut().insert(rejected);
+            }
+        }
+    }
+    next.run(request).await
+}
+
+#[derive(Deserialize)]
+pub(crate) struct LoginRequest {
+    username: String,
+    password: String,
+}
+
+#[derive(Serialize)]
+pub(crate) struct LoginResponse {
+    token: String,
+    token_type: &'static str,
+    expires_at: DateTime<Utc>,
+}
+
+// Swap a username and password for a bearer token. Failures are counted per
+// username and per address, which needs the server started with
+// `into_make_service_with_connect_info`.
+pub(crate) async fn login(
+    State(state): State<AppState>,
+    addr: Option<ConnectInfo<SocketAddr>>,
+    ApiJson(login): ApiJson<LoginRequest>,
+) -> Result<Json<LoginResponse>, ApiError> {
+    let ip = addr.map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |ConnectInfo(addr)| addr.ip());
+
+    // Checking a password hash takes a while, so it's kept off the async
+    // threads
+    let auth = state.auth.clone();
+    let username = login.username.clone();
+    let result = tokio::task::spawn_blocking(move || auth.log_in(&username, &login.password, ip))
+        .await
+        .unwrap_or(LoginResult::Failure);
+    match result {
+        LoginResult::Success { .. } => {}
+        LoginResult::Denied => {
+            tracing::info!(username = login.username, "Denied login");
+            return Err(ApiError::Forbidden("Your account has been disabled".to_string()));
+        }
+        LoginResult::Failure => {
+            tracing::info!(username = login.username, "Failed login");
+            return Err(ApiError::Unauthorized("Wrong username or password".to_string()));
+        }
+        LoginResult::LockedOut { until } => {
+            tracing::info!(username = login.username, %ip, %until, "Locked out login");
+            return Err(ApiError::Locked(until));
+        }
+    }
+
+    let now = Utc::now();
+    let expires_at = state.auth.expires_at(now)?;
+    let claims = Claims {
+        sub: login.username,
+        iat: now.timestamp(),
+        exp: expires_at.timestamp(),
+    };
+    Ok(Json(LoginResponse {
+        token: state.auth.issue(&claims),
+        token_type: "Bearer",
+        // Tokens only carry whole seconds
+        expires_at: Utc.timestamp_opt(claims.exp, 0).single().unwrap_or(expires_at),
+    }))
+}
+
+// Lift a username's lockout, e.g. `DELETE /auth/lockouts/users/herbert`.
+// Only admins can.
+pub(crate) async fn unlock_user(
+    State(state): State<AppState>,
+    user: AuthUser,
+    ApiPath(username): ApiPath<String>,
+) -> Result<StatusCode, ApiError> {
+    user.require(Permission::UnlockLogins, None)?;
+    unlocked(state.auth.lockouts.unlock_user(&username))
+}
+
+// Lift an address's lockout, e.g. `DELETE /auth/lockouts/ips/192.0.2.1`.
+// Only admins can.
+pub(crate) async fn unlock_ip(
+    State(state): State<AppState>,
+    user: AuthUser,
+    ApiPath(ip): ApiPath<IpAddr>,
+) -> Result<StatusCode, ApiError> {
+    user.require(Permission::UnlockLogins, None)?;
+    unlocked(state.auth.lockouts.unlock_ip(ip))
+}
+
+fn unlocked(unlocked: bool) -> Result<StatusCode, ApiError> {
+    match unlocked {
+        true => Ok(StatusCode::NO_CONTENT),
+        false => Err(ApiError::NotFound),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::response::IntoResponse;
+    use axum::routing::get;
+    use axum::Router;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::User;
+
+    fn auth(token_lifetime: Duration) -> Auth {
+        let users = Users::new(vec![User {
+            username: "herbert".to_string(),
+            password: "whales".to_string(),
+            role: Role::Author,
+        }]);
+        Auth::new(users, Some("a secret"), token_lifetime, no_lockouts())
+    }
+
+    fn no_lockouts() -> LockoutPolicy {
+        LockoutPolicy {
+            max_failures: 0,
+            window: Duration::minutes(15),
+            lockout: Duration::minutes(1),
+            max_lockout: Duration::hours(1),
+        }
+    }
+
+    fn claims(expires_in: Duration) -> Claims {
+        let now = Utc::now();
+        Claims {
+            sub: "herbert".to_string(),
+            iat: now.timestamp(),
+            exp: (now + expires_in).timestamp(),
+        }
+    }
+
+    // A token with `header` and `claims`, signed by `auth`
+    fn sign(auth: &Auth, header: &str, claims: &Claims) -> String {
+        let payload = serde_json::to_vec(claims).unwrap();
+        let signed = format!("{}.{}", URL_SAFE_NO_PAD.encode(header), URL_SAFE_NO_PAD.encode(payload));
+        let mut mac = auth.mac();
+        mac.update(signed.as_bytes());
+        format!("{signed}.{}", URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes()))
+    }
+
+    // Who `authenticate` says sent a request with `authorization`, or the
+    // status and `WWW-Authenticate` header it was refused with
+    async fn whoami(auth: Auth, authorization: Option<&str>) -> Result<String, (StatusCode, Option<String>)> {
+        let app = Router::new()
+            .route("/me", get(|user: AuthUser| async move { user.username }))
+            .layer(axum::middleware::from_fn_with_state(Arc::new(auth), authenticate));
+        let mut request = Request::get("/me");
+        if let Some(authorization) = authorization {
+            request = request.header(header::AUTHORIZATION, authorization);
+        }
+        let response = app.oneshot(request.body(Body::empty()).unwrap()).await.unwrap();
+        let status = response.status();
+        let challenge = response.headers().get(header::WWW_AUTHENTICATE);
+        let challenge = challenge.map(|value| value.to_str().unwrap().to_string());
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        match status {
+            StatusCode::OK => Ok(String::from_utf8(body.to_vec()).unwrap()),
+            _ => Err((status, challenge)),
+        }
+    }
+
+    #[test]
+    fn issued_tokens_verify() {
+        let auth = auth(Duration::hours(1));
+        let token = auth.issue(&claims(Duration::hours(1)));
+        assert_eq!(auth.verify(&token).ok().map(|claims| claims.sub), Some("herbert".to_string()));
+    }
+
+    #[test]
+    fn expired_tokens_are_rejected() {
+        let auth = auth(Duration::hours(1));
+        let token = auth.issue(&claims(Duration::seconds(-1)));
+        assert!(matches!(auth.verify(&token), Err(TokenRejected::Expired)));
+    }
+
+    #[test]
+    fn tampered_tokens_are_rejected() {
+        let auth = auth(Duration::hours(1));
+        let token = auth.issue(&claims(Duration::hours(1)));
+        let (header, rest) = token.split_once('.').unwrap();
+        let (_, signature) = rest.split_once('.').unwrap();
+
+        let mut admin = claims(Duration::hours(1));
+        admin.sub = "admin".to_string();
+        let other_payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&admin).unwrap());
+        let swapped_payload = format!("{header}.{other_payload}.{signature}");
+        assert!(matches!(auth.verify(&swapped_payload), Err(TokenRejected::Invalid)));
+
+        let mut bytes = URL_SAFE_NO_PAD.decode(signature).unwrap();
+        bytes[0] ^= 1;
+        let flipped_signature = format!("{}.{}", token.rsplit_once('.').unwrap().0, URL_SAFE_NO_PAD.encode(bytes));
+        assert!(matches!(auth.verify(&flipped_signature), Err(TokenRejected::Invalid)));
+
+        let other_secret = Auth::new(Users::default(), Some("another secret"), Duration::hours(1), no_lockouts());
+        assert!(matches!(other_secret.verify(&token), Err(TokenRejected::Invalid)));
+    }
+
+    #[test]
+    fn other_algorithms_are_rejected() {
+        let auth = auth(Duration::hours(1));
+        let claims = claims(Duration::hours(1));
+
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims).unwrap());
+        let unsigned = format!("{}.{payload}.", URL_SAFE_NO_PAD.encode(r#"{"alg":"none","typ":"JWT"}"#));
+        assert!(matches!(auth.verify(&unsigned), Err(TokenRejected::Invalid)));
+
+        // Even signed with the right key, only the exact header we issue is
+        // accepted
+        let headers = [r#"{"alg":"HS512","typ":"JWT"}"#, r#"{"alg":"none","typ":"JWT"}"#, r#"{"typ":"JWT","alg":"HS256"}"#];
+        for header in headers {
+            assert!(matches!(auth.verify(&sign(&auth, header, &claims)), Err(TokenRejected::Invalid)), "{header}");
+        }
+        assert!(auth.verify(&sign(&auth, TOKEN_HEADER, &claims)).is_ok());
+    }
+
+    #[test]
+    fn overlong_lifetimes_are_a_server_error() {
+        let auth = auth(Duration::max_value());
+        let error = auth.expires_at(Utc::now()).unwrap_err();
+        assert_eq!(error.into_response().status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn requests_are_authenticated_by_their_bearer_token() {
+        let token = auth(Duration::hours(1)).issue(&claims(Duration::hours(1)));
+        assert_eq!(whoami(auth(Duration::hours(1)), Some(&format!("Bearer {token}"))).await, Ok("herbert".to_string()));
+
+        let expired = auth(Duration::hours(1)).issue(&claims(Duration::seconds(-1)));
+        for authorization in [
+            None,
+            Some("Bearer"),
+            Some("Bearer not.a.token"),
+            Some(format!("Basic {token}").as_str()),
+            Some(format!("bearer{token}").as_str()),
+            Some(format!("Bearer {expired}").as_str()),
+        ] {
+            let refused = whoami(auth(Duration::hours(1)), authorization).await;
+            assert_eq!(refused, Err((StatusCode::UNAUTHORIZED, Some("Bearer".to_string()))), "{authorization:?}");
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::hash_map::DefaultHasher;
+use std::convert::Infallible;
+use std::fmt::Display;
+use std::hash::{Hash, Hasher};
+
+use async_trait::async_trait;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Duration, DurationRound, Utc};
+
+use crate::StoreVersion;
+
+/// What a client can use to check whether its cached copy of a response is
+/// still current: a strong ETag, and when the response last changed.
+pub struct Validators {
+    etag: String,
+    last_modified: DateTime<Utc>,
+}
+
+impl Validators {
+    /// Validators for a response body, with the ETag hashed from its bytes.
+    pub fn for_content(content: &[u8], last_modified: DateTime<Utc>) -> Self {
+        Self {
+            etag: etag(content),
+            last_modified,
+        }
+    }
+
+    /// Validators for a response that could depend on anything in the store,
+    /// such as a list of posts. They change whenever the store does, so the
+    /// response itself needn't be built to check them. `resource` tells
+    /// responses apart: usually it's the request's URI, but a response that
+    /// varies with anything else, such as the host it was asked for, needs
+    /// that in it too.
+    pub fn for_store(version: StoreVersion, resource: impl Display) -> Self {
+        Self {
+            etag: etag((version.version, version.modified, resource.to_string())),
+            last_modified: version.modified,
+        }
+    }
+
+    /// An empty 304 Not Modified response.
+    pub fn not_modified(&self) -> Response {
+        self.attach(StatusCode::NOT_MODIFIED)
+    }
+
+    /// A response, with headers carrying the validators.
+    pub fn attach(&self, response: impl IntoResponse) -> Response {
+        let mut response = response.into_response();
+        let headers = response.headers_mut();
+        if let Ok(value) = HeaderValue::from_str(&self.etag) {
+            headers.insert(header::ETAG, value);
+        }
+        if let Ok(value) = HeaderValue::from_str(&http_date(self.last_modified)) {
+            headers.insert(header::LAST_MODIFIED, value);
+        }
+        response
+    }
+}
+
+// A quoted, strong ETag. The hash is only stable for one build of the
+// server, which just means clients fetch everything once after an upgrade.
+fn etag(content: impl Hash) -> String {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+// Format a time the way HTTP headers want it, e.g.
+// `Sat, 17 Oct 2026 07:02:18 GMT`.
+fn http_date(date: DateTime<Utc>) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// The conditional headers of a request: `If-None-Match` and
+/// `If-Modified-Since` for GETs, and `If-Match` for changes. Headers that
+/// can't be parsed are ignored, as HTTP requires.
+pub struct Conditions {
+    if_none_match: Option<String>,
+    if_modified_since: Option<DateTime<Utc>>,
+    if_match: Option<String>,
+}
+
+impl Conditions {
+    /// Is the client's copy still current? If so, answer with a 304.
+    pub fn is_fresh(&self, validators: &Validators) -> bool {
+        // If-None-Match wins when both are sent
+        if let Some(tags) = &self.if_none_match {
+            return tags.split(',').map(str::trim).any(|tag| {
+                // GETs use weak comparison, so a W/ prefix doesn't matter
+                tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == validators.etag
+            });
+        }
+        match self.if_modified_since {
+            // HTTP dates only have whole seconds
+            Some(since) => {
+                let modified = validators.last_modified.duration_trunc(Duration::seconds(1));
+                modified.is_ok_and(|modified| modified <= since)
+            }
+            None => false,
+        }
+    }
+
+    /// Did the client send `If-Match`?
+    pub fn has_if_match(&self) -> bool {
+        self.if_match.is_some()
+    }
+
+    /// Does `If-Match` allow changing a resource with these validators? It
+    /// doesn't if the header is missing.
+    pub fn if_match(&self, validators: &Validators) -> bool {
+        self.if_match.as_deref().is_some_and(|tags| {
+            // Changes use strong comparison, so weak ETags never match
+            tags.split(',').map(str::trim).any(|tag| tag == "*" || tag == validators.etag)
+        })
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Conditions {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let value_of = |name| parts.headers.get(name).and_then(|value: &HeaderValue| value.to_str().ok());
+        Ok(Self {
+            if_none_match: value_of(header::IF_NONE_MATCH).map(str::to_string),
+            if_modified_since: value_of(header::IF_MODIFIED_SINCE)
+                .and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+                .map(|date| date.with_timezone(&Utc)),
+            if_match: value_of(header::IF_MATCH).map(str::to_string),
+        })
+    }
+}
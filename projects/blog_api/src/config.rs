@@ -0,0 +1,52 @@
+use std::sync::Arc;
+
+use axum::extract::DefaultBodyLimit;
+use axum::Router;
+use chrono::Duration;
+use server_config::Config;
+
+use crate::{router, Auth, Budget, LockoutPolicy, PostStore, RateLimiter, Users};
+
+/// Build `router` on top of a post store, with the rate limits, users,
+/// logins and body limit that `config` asks for. It's only an error if the
+/// users file can't be loaded.
+pub fn router_from_config(store: Arc<dyn PostStore>, config: &Config) -> Result<Router, String> {
+    // Each client gets its own budget of reads and writes
+    let limiter = RateLimiter::new(
+        Budget {
+            rate: config.read_rate,
+            burst: config.read_burst,
+        },
+        Budget {
+            rate: config.write_rate,
+            burst: config.write_burst,
+        },
+    );
+
+    // Only the users in the users file can log in and make changes
+    let users = match &config.users_file {
+        Some(path) => Users::load(path)?,
+        None => {
+            tracing::warn!("There's no users file, so nobody can log in");
+            Users::default()
+        }
+    };
+    let lockout = LockoutPolicy {
+        max_failures: config.login_max_failures,
+        window: seconds(config.login_failure_window),
+        lockout: seconds(config.login_lockout),
+        max_lockout: seconds(config.login_max_lockout),
+    };
+    let auth = Auth::new(users, config.token_secret.as_deref(), seconds(config.token_lifetime), lockout);
+
+    Ok(router(store, limiter, auth).layer(DefaultBodyLimit::max(config.body_limit)))
+}
+
+// A duration from the config. They're a year at most, which
+// `Config::validate` checks.
+fn seconds(seconds: u64) -> Duration {
+    i64::try_from(seconds)
+        .ok()
+        .and_then(Duration::try_seconds)
+        .expect("A duration from the config is in range")
+}
@@ -0,0 +1,205 @@
+use axum::extract::rejection::{JsonRejection, PathRejection, QueryRejection};
+use axum::extract::FromRequest;
+use axum::extract::FromRequestParts;
+use axum::http::{header, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+
+use crate::current_request_id;
+
+/// Everything that can go wrong while handling a blog request.
+///
+/// Each variant turns into a JSON body of the form
+/// `{ "code": "...", "message": "...", "request_id": "..." }`. The `code`
+/// values are part of the API: clients match on them, so don't change them.
+#[derive(Debug)]
+pub enum ApiError {
+    /// The post (or other resource) doesn't exist.
+    NotFound,
+    /// The request couldn't be understood, for example a non-numeric ID.
+    BadRequest(String),
+    /// The request body wasn't valid JSON, or didn't have the right shape.
+    InvalidJson(String),
+    /// The client isn't logged in, or tried to log in and failed.
+    Unauthorized(String),
+    /// The client is logged in, but their role doesn't allow what they tried.
+    Forbidden(String),
+    /// There have been too many failed logins for the username, or from the
+    /// client's address, so logging in is locked until this time.
+    Locked(DateTime<Utc>),
+    /// The request body was bigger than the server's `body_limit`.
+    PayloadTooLarge,
+    /// The client tried to change a post that someone else has changed since
+    /// they fetched it.
+    PreconditionFailed,
+    /// The client tried to change a post without saying which version of it
+    /// they're changing.
+    PreconditionRequired,
+    /// The client has used up its request budget, and should wait this many
+    /// seconds before trying again.
+    TooManyRequests(u64),
+    /// The database failed. The details are logged, not sent to the client.
+    Database(String),
+    /// The server is misconfigured, or has a bug. The details are logged, not
+    /// sent to the client.
+    Internal(String),
+}
+
+impl ApiError {
+    /// Wrap a database error, for use with `map_err`.
+    pub fn database(error: impl std::fmt::Display) -> Self {
+        ApiError::Database(error.to_string())
+    }
+
+    fn status(&self) -> StatusCode {
+        match self {
+            ApiError::NotFound => StatusCode::NOT_FOUND,
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::InvalidJson(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::Locked(_) => StatusCode::LOCKED,
+            ApiError::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            ApiError::PreconditionRequired => StatusCode::PRECONDITION_REQUIRED,
+            ApiError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Database(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn code(&self) -> &'static str {
+        match self {
+            ApiError::NotFound => "not_found",
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::InvalidJson(_) => "invalid_json",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::Locked(_) => "locked_out",
+            ApiError::PayloadTooLarge => "payload_too_large",
+            ApiError::PreconditionFailed => "precondition_failed",
+            ApiError::PreconditionRequired => "precondition_required",
+            ApiError::TooManyRequests(_) => "too_many_requests",
+            ApiError::Database(_) => "database_unavailable",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            ApiError::NotFound => "The requested resource does not exist".to_string(),
+            ApiError::BadRequest(message) => message.clone(),
+            ApiError::InvalidJson(message) => message.clone(),
+            ApiError::Unauthorized(message) => message.clone(),
+            ApiError::Forbidden(message) => message.clone(),
+            ApiError::Locked(until) => format!(
+                "Too many failed logins; try again after {}",
+                until.to_rfc3339_opts(SecondsFormat::Secs, true)
+            ),
+            ApiError::PayloadTooLarge => "The request body is too large".to_string(),
+            ApiError::PreconditionFailed => {
+                "The post has changed since you fetched it; fetch it again and reapply your changes".to_string()
+            }
+            ApiError::PreconditionRequired => {
+                "Say which version of the post you're changing, with If-Match or a version".to_string()
+            }
+            ApiError::TooManyRequests(seconds) => {
+                format!("You're sending requests too quickly; wait {seconds}s and try again")
+            }
+            ApiError::Database(_) => "The database is unavailable, please try again later".to_string(),
+            ApiError::Internal(_) => "Something went wrong on the server".to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApiError::Database(details) | ApiError::Internal(details) => write!(f, "{}: {details}", self.code()),
+            _ => write!(f, "{}: {}", self.code(), self.message()),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    code: &'static str,
+    message: String,
+    request_id: String,
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        // The request's span carries its ID, so it needn't be repeated here
+        match &self {
+            ApiError::Database(details) => tracing::error!("Database error: {details}"),
+            ApiError::Internal(details) => tracing::error!("Internal error: {details}"),
+            _ => {}
+        }
+        let body = ErrorBody {
+            code: self.code(),
+            message: self.message(),
+            request_id: current_request_id(),
+        };
+        let mut response = (self.status(), Json(body)).into_response();
+        match self {
+            ApiError::Unauthorized(_) => {
+                response.headers_mut().insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            }
+            ApiError::TooManyRequests(seconds) => {
+                response.headers_mut().insert(header::RETRY_AFTER, seconds.into());
+            }
+            ApiError::Locked(until) => {
+                // Round up, so a client that waits this long finds it unlocked
+                let millis = (until - Utc::now()).num_milliseconds().max(0);
+                let seconds = (millis + 999) / 1000;
+                response.headers_mut().insert(header::RETRY_AFTER, seconds.into());
+            }
+            _ => {}
+        }
+        response
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE {
+            return ApiError::PayloadTooLarge;
+        }
+        ApiError::InvalidJson(rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(rejection: PathRejection) -> Self {
+        ApiError::BadRequest(rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        ApiError::BadRequest(rejection.body_text())
+    }
+}
+
+/// A drop-in replacement for `axum::Json` that reports bad bodies as an
+/// `ApiError` instead of axum's plain-text rejection.
+#[derive(FromRequest)]
+#[from_request(via(axum::Json), rejection(ApiError))]
+pub struct ApiJson<T>(pub T);
+
+/// A drop-in replacement for `axum::extract::Path` that reports bad path
+/// parameters as an `ApiError`.
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Path), rejection(ApiError))]
+pub struct ApiPath<T>(pub T);
+
+/// A drop-in replacement for `axum::extract::Query` that reports bad query
+/// strings as an `ApiError`.
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(ApiError))]
+pub struct ApiQuery<T>(pub T);
@@ -0,0 +1,307 @@
+use axum::extract::{Host, State};
+use axum::http::{header, Uri};
+use axum::response::Response;
+use blog_model::BlogPost;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Deserialize;
+
+use crate::{ApiError, ApiQuery, AppState, Conditions, ListParams, SortField, SortOrder, Validators};
+
+// How many of the latest posts a feed holds
+const FEED_SIZE: usize = 20;
+
+const SITE_TITLE: &str = "Blog";
+
+#[derive(Deserialize)]
+pub(crate) struct FeedParams {
+    author: Option<String>,
+    tag: Option<String>,
+}
+
+// Everything both kinds of feed are built from
+struct Feed {
+    title: String,
+    site_url: String,
+    self_url: String,
+    // The newest post's date, or `None` if there are no posts
+    updated: Option<DateTime<Utc>>,
+    entries: Vec<FeedEntry>,
+}
+
+struct FeedEntry {
+    post: BlogPost,
+    url: String,
+    body_html: String,
+}
+
+impl Feed {
+    async fn load(state: &AppState, site_url: String, self_url: String, params: FeedParams) -> Result<Self, ApiError> {
+        let mut title = SITE_TITLE.to_string();
+        if let Some(author) = &params.author {
+            title.push_str(&format!(": posts by {author}"));
+        }
+        if let Some(tag) = &params.tag {
+            title.push_str(&format!(": tagged {tag}"));
+        }
+
+        let query = ListParams {
+            limit: Some(FEED_SIZE),
+            sort: Some(SortField::Date),
+            order: Some(SortOrder::Desc),
+            author: params.author,
+            tag: params.tag,
+            ..ListParams::default()
+        }
+        .try_into()?;
+        let posts = state.store.list(&query).await?.posts;
+
+        Ok(Self {
+            title,
+            self_url,
+            updated: posts.iter().map(|post| post.date).max(),
+            entries: posts
+                .into_iter()
+                .map(|post| FeedEntry {
+                    url: format!("{site_url}/posts/{}", post.id),
+                    body_html: state.rendered.html(&post),
+                    post,
+                })
+                .collect(),
+            site_url,
+        })
+    }
+
+    fn to_rss(&self) -> String {
+        let mut xml = String::from(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+        xml.push_str("\n<rss version=\"2.0\" xmlns:atom=\"http://www.w3.org/2005/Atom\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n<channel>\n");
+        xml.push_str(&format!("<title>{}</title>\n", escape(&self.title)));
+        xml.push_str(&format!("<link>{}/</link>\n", escape(&self.site_url)));
+        xml.push_str(&format!("<description>{}</description>\n", escape(&self.title)));
+        xml.push_str(&format!(
+            "<atom:link href=\"{}\" rel=\"self\" type=\"application/rss+xml\"/>\n",
+            escape(&self.self_url)
+        ));
+        if let Some(updated) = self.updated {
+            xml.push_str(&format!("<lastBuildDate>{}</lastBuildDate>\n", updated.to_rfc2822()));
+        }
+        for entry in &self.entries {
+            let post = &entry.post;
+            xml.push_str("<item>\n");
+            xml.push_str(&format!("<title>{}</title>\n", escape(&post.title)));
+            xml.push_str(&format!("<link>{}</link>\n", escape(&entry.url)));
+            xml.push_str(&format!("<guid isPermaLink=\"true\">{}</guid>\n", escape(&entry.url)));
+            xml.push_str(&format!("<pubDate>{}</pubDate>\n", post.date.to_rfc2822()));
+            // RSS's own <author> must be an email address
+            xml.push_str(&format!("<dc:creator>{}</dc:creator>\n", escape(&post.author)));
+            for tag in &post.tags {
+                xml.push_str(&format!("<category>{}</category>\n", escape(tag)));
+            }
+            xml.push_str(&format!("<description>{}</description>\n", escape(&entry.body_html)));
+            xml.push_str("</item>\n");
+        }
+        xml.push_str("</channel>\n</rss>\n");
+        xml
+    }
+
+    fn to_atom(&self) -> String {
+        // A feed must have an updated time even when it's empty
+        let updated = self.updated.unwrap_or(DateTime::<Utc>::UNIX_EPOCH);
+
+        let mut xml = String::from(r#"<?xml version="1.0" encoding="utf-8"?>"#);
+        xml.push_str("\n<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+        xml.push_str(&format!("<title>{}</title>\n", escape(&self.title)));
+        xml.push_str(&format!("<id>{}</id>\n", escape(&self.self_url)));
+        xml.push_str(&format!("<updated>{}</updated>\n", atom_date(updated)));
+        xml.push_str(&format!("<link href=\"{}/\"/>\n", escape(&self.site_url)));
+        xml.push_str(&format!("<link href=\"{}\" rel=\"self\"/>\n", escape(&self.self_url)));
+        for entry in &self.entries {
+            let post = &entry.post;
+            xml.push_str("<entry>\n");
+            xml.push_str(&format!("<title>{}</title>\n", escape(&post.title)));
+            xml.push_str(&format!("<id>{}</id>\n", escape(&entry.url)));
+            xml.push_str(&format!("<link href=\"{}\"/>\n", escape(&entry.url)));
+            xml.push_str(&format!("<published>{}</published>\n", atom_date(post.date)));
+            xml.push_str(&format!("<updated>{}</updated>\n", atom_date(post.date)));
+            xml.push_str(&format!("<author><name>{}</name></author>\n", escape(&post.author)));
+            for tag in &post.tags {
+                xml.push_str(&format!("<category term=\"{}\"/>\n", escape(tag)));
+            }
+            xml.push_str(&format!("<content type=\"html\">{}</content>\n", escape(&entry.body_html)));
+            xml.push_str("</entry>\n");
+        }
+        xml.push_str("</feed>\n");
+        xml
+    }
+}
+
+// The site's URL and the feed's own. Feeds need absolute URLs, and the Host
+// header is the best guess at what the reader used to reach us.
+fn feed_urls(host: Option<Host>, uri: &Uri) -> (String, String) {
+    let host = host.map_or_else(|| "localhost".to_string(), |Host(host)| host);
+    let site_url = format!("http://{host}");
+    let self_url = format!("{site_url}{uri}");
+    (site_url, self_url)
+}
+
+// The latest posts as RSS 2.0. `?author=` and `?tag=` narrow it down.
+pub(crate) async fn rss_feed(
+    State(state): State<AppState>,
+    conditions: Conditions,
+    host: Option<Host>,
+    uri: Uri,
+    ApiQuery(params): ApiQuery<FeedParams>,
+) -> Result<Response, ApiError> {
+    // The links depend on the host, so the validators do too
+    let (site_url, self_url) = feed_urls(host, &uri);
+    let validators = Validators::for_store(state.store.version().await?, &self_url);
+    if conditions.is_fresh(&validators) {
+        return Ok(validators.not_modified());
+    }
+    let feed = Feed::load(&state, site_url, self_url, params).await?;
+    Ok(validators.attach(([(header::CONTENT_TYPE, "application/rss+xml; charset=utf-8")], feed.to_rss())))
+}
+
+// The latest posts as Atom. `?author=` and `?tag=` narrow it down.
+pub(crate) async fn atom_feed(
+    State(state): State<AppState>,
+    conditions: Conditions,
+    host: Option<Host>,
+    uri: Uri,
+    ApiQuery(params): ApiQuery<FeedParams>,
+) -> Result<Response, ApiError> {
+    // The links depend on the host, so the validators do too
+    let (site_url, self_url) = feed_urls(host, &uri);
+    let validators = Validators::for_store(state.store.version().await?, &self_url);
+    if conditions.is_fresh(&validators) {
+        return Ok(validators.not_modified());
+    }
+    let feed = Feed::load(&state, site_url, self_url, params).await?;
+    Ok(validators.attach(([(header::CONTENT_TYPE, "application/atom+xml; charset=utf-8")], feed.to_atom())))
+}
+
+fn atom_date(date: DateTime<Utc>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+// Escape text for use in XML content or attribute values
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            // Control characters aren't allowed in XML 1.0 at all
+            c if c.is_control() && !matches!(c, '\n' | '\r' | '\t') => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Text that breaks XML, or a CDATA section, if it isn't escaped
+    const NASTY: &str = "Fish & <chips> ]]> \"quoted\" 'too'";
+
+    fn feed() -> Feed {
+        let post = |id, date: &str, title: &str| BlogPost {
+            id,
+            date: date.parse().unwrap(),
+            title: title.to_string(),
+            body: NASTY.to_string(),
+            author: "herbert & co".to_string(),
+            tags: vec!["a<b".to_string()],
+            version: 1,
+        };
+        let entries = [post(2, "2023-09-27T08:00:00Z", NASTY), post(1, "2023-09-26T10:30:00Z", "First")]
+            .into_iter()
+            .map(|post| FeedEntry {
+                url: format!("http://localhost/posts/{}", post.id),
+                body_html: format!("<p>{}</p>", post.body),
+                post,
+            })
+            .collect();
+        Feed {
+            title: "Blog: tagged a<b".to_string(),
+            site_url: "http://localhost".to_string(),
+            self_url: "http://localhost/feed.rss?tag=a%3Cb&author=x".to_string(),
+            updated: Some("2023-09-27T08:00:00Z".parse().unwrap()),
+            entries,
+        }
+    }
+
+    fn text<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> &'a str {
+        let child = node.children().find(|child| child.tag_name().name() == name).unwrap();
+        child.text().unwrap_or_default()
+    }
+
+    #[test]
+    fn rss_is_well_formed_with_rfc_822_dates() {
+        let xml = feed().to_rss();
+        let doc = roxmltree::Document::parse(&xml).unwrap();
+        let channel = doc.descendants().find(|node| node.has_tag_name("channel")).unwrap();
+        assert_eq!(text(channel, "title"), "Blog: tagged a<b");
+
+        let last_build = DateTime::parse_from_rfc2822(text(channel, "lastBuildDate")).unwrap();
+        assert_eq!(last_build, "2023-09-27T08:00:00Z".parse::<DateTime<Utc>>().unwrap());
+
+        let items: Vec<_> = channel.children().filter(|node| node.has_tag_name("item")).collect();
+        assert_eq!(items.len(), 2);
+        assert_eq!(text(items[0], "title"), NASTY);
+        assert_eq!(text(items[0], "description"), format!("<p>{NASTY}</p>"));
+        assert_eq!(text(items[0], "creator"), "herbert & co");
+        assert_eq!(text(items[0], "category"), "a<b");
+        for (item, date) in items.iter().zip(["2023-09-27T08:00:00Z", "2023-09-26T10:30:00Z"]) {
+            let published = DateTime::parse_from_rfc2822(text(*item, "pubDate")).unwrap();
+            assert_eq!(published, date.parse::<DateTime<Utc>>().unwrap());
+        }
+    }
+
+    #[test]
+    fn atom_is_well_formed_with_rfc_3339_dates() {
+        let xml = feed().to_atom();
+        let doc = roxmltree::Document::parse(&xml).unwrap();
+        let root = doc.root_element();
+        assert_eq!(root.tag_name().namespace(), Some("http://www.w3.org/2005/Atom"));
+        assert_eq!(text(root, "updated"), "2023-09-27T08:00:00Z");
+        DateTime::parse_from_rfc3339(text(root, "updated")).unwrap();
+
+        let entries: Vec<_> = root.children().filter(|node| node.has_tag_name("entry")).collect();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(text(entries[0], "title"), NASTY);
+        assert_eq!(text(entries[0], "content"), format!("<p>{NASTY}</p>"));
+        assert_eq!(text(entries[1], "published"), "2023-09-26T10:30:00Z");
+        DateTime::parse_from_rfc3339(text(entries[1], "updated")).unwrap();
+
+        let category = entries[0].children().find(|node| node.has_tag_name("category")).unwrap();
+        assert_eq!(category.attribute("term"), Some("a<b"));
+        let self_link = root.children().find(|node| node.attribute("rel") == Some("self")).unwrap();
+        assert_eq!(self_link.attribute("href"), Some("http://localhost/feed.rss?tag=a%3Cb&author=x"));
+    }
+
+    #[test]
+    fn empty_feeds_are_valid() {
+        let feed = Feed {
+            updated: None,
+            entries: vec![],
+            ..feed()
+        };
+        let rss = feed.to_rss();
+        let doc = roxmltree::Document::parse(&rss).unwrap();
+        assert!(!doc.descendants().any(|node| node.has_tag_name("lastBuildDate")));
+
+        let atom = feed.to_atom();
+        let doc = roxmltree::Document::parse(&atom).unwrap();
+        assert_eq!(text(doc.root_element(), "updated"), "1970-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn control_characters_are_dropped() {
+        assert_eq!(escape("a\u{0}b\u{1b}c\td"), "abc\td");
+    }
+}
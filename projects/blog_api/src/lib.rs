@@ -0,0 +1,45 @@
+//! Code shared by `blog_server` and `blog_server_db`, so that both servers
+//! behave the same way from a client's point of view. Each server only has
+//! to provide a `PostStore`, a `RateLimiter` and an `Auth`, hand them to
+//! `router` (or just hand the store and its `Config` to
+//! `router_from_config`), and `serve` the result. As well as the JSON API,
+//! the router serves HTML pages for reading the blog in a browser, RSS and
+//! Atom feeds, and Prometheus metrics.
+
+mod auth;
+mod conditional;
+mod config;
+mod error;
+mod feed;
+mod list;
+mod lockout;
+mod markdown;
+mod metrics;
+mod pages;
+mod rate_limit;
+mod request_id;
+mod roles;
+mod routes;
+mod search;
+mod serve;
+mod store;
+#[cfg(feature = "testing")]
+pub mod store_tests;
+mod users;
+
+pub use auth::{Auth, AuthUser};
+pub use conditional::{Conditions, Validators};
+pub use config::router_from_config;
+pub use error::{ApiError, ApiJson, ApiPath, ApiQuery};
+pub use list::{Cursor, ListParams, ListQuery, SortField, SortOrder, DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE};
+pub use lockout::{LockoutPolicy, Lockouts};
+pub use markdown::{render_markdown, RenderCache};
+pub use metrics::{Histogram, MetricsText, RequestMetrics, LATENCY_BUCKETS};
+pub use rate_limit::{Budget, RateLimitKey, RateLimiter};
+pub use request_id::{current_request_id, request_id};
+pub use roles::{Grant, Permission, Role};
+pub use routes::{router, AppState};
+pub use search::{snippet_to_html, words, SearchQuery, SearchTerm, MARK_END, MARK_START};
+pub use serve::serve;
+pub use store::{normalize_tags, PostStore, StoreVersion};
+pub use users::{LoginResult, User, Users};
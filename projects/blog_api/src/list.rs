@@ -0,0 +1,360 @@
+use std::cmp::Ordering;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use blog_model::BlogPost;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::ApiError;
+
+/// How many posts a page holds if the client doesn't say.
+pub const DEFAULT_PAGE_SIZE: usize = 20;
+
+/// The most posts a client may ask for in one page.
+pub const MAX_PAGE_SIZE: usize = 100;
+
+/// Which field `/blog/all` sorts by. Ties are always broken by ID, so every
+/// ordering is total and keyset pagination never skips or repeats a post.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortField {
+    #[default]
+    Id,
+    Date,
+    Title,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// The sort key of the last post on a page. The next page starts with the
+/// first post that sorts after it. A cursor without the key it's sorted by
+/// sorts after every post, the way a missing key would, so nothing follows
+/// it in ascending order and everything does in descending order.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Cursor {
+    pub sort: SortField,
+    pub order: SortOrder,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    pub id: i32,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).expect("A cursor always serializes");
+        URL_SAFE_NO_PAD.encode(json)
+    }
+
+    fn decode(cursor: &str) -> Option<Self> {
+        let json = URL_SAFE_NO_PAD.decode(cursor).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+}
+
+/// The query string accepted by `/blog/all`, before it has been checked.
+#[derive(Deserialize, Debug, Default)]
+pub struct ListParams {
+    pub limit: Option<usize>,
+    pub cursor: Option<String>,
+    pub sort: Option<SortField>,
+    pub order: Option<SortOrder>,
+    pub author: Option<String>,
+    pub tag: Option<String>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+}
+
+/// A checked request for one page of posts.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ListQuery {
+    pub limit: usize,
+    pub sort: SortField,
+    pub order: SortOrder,
+    /// Only posts by this author, ignoring (ASCII) case.
+    pub author: Option<String>,
+    /// Only posts carrying this tag.
+    pub tag: Option<String>,
+    /// Only posts dated at or after this time.
+    pub since: Option<DateTime<Utc>>,
+    /// Only posts dated before this time.
+    pub until: Option<DateTime<Utc>>,
+    /// Only posts that sort after this one.
+    pub after: Option<Cursor>,
+}
+
+impl Default for ListQuery {
+    fn default() -> Self {
+        Self {
+            limit: DEFAULT_PAGE_SIZE,
+            sort: SortField::default(),
+            order: SortOrder::default(),
+            author: None,
+            tag: None,
+            since: None,
+            until: None,
+            after: None,
+        }
+    }
+}
+
+impl TryFrom<ListParams> for ListQuery {
+    type Error = ApiError;
+
+    fn try_from(params: ListParams) -> Result<Self, Self::Error> {
+        let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+        if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+            return Err(ApiError::BadRequest(format!("limit must be between 1 and {MAX_PAGE_SIZE}")));
+        }
+        let sort = params.sort.unwrap_or_default();
+        let order = params.order.unwrap_or_default();
+
+        // A cursor only makes sense for the ordering that produced it
+        let after = match params.cursor {
+            None => None,
+            Some(cursor) => {
+                let cursor = Cursor::decode(&cursor)
+                    .filter(|cursor| cursor.sort == sort && cursor.order == order)
+                    .ok_or_else(|| ApiError::BadRequest("cursor is invalid for this query".to_string()))?;
+                Some(cursor)
+            }
+        };
+
+        Ok(Self {
+            limit,
+            sort,
+            order,
+            author: params.author,
+            tag: params.tag.map(|tag| tag.trim().to_lowercase()),
+            since: params.since.as_deref().map(|date| parse_date("since", date)).transpose()?,
+            until: params.until.as_deref().map(|date| parse_date("until", date)).transpose()?,
+            after,
+        })
+    }
+}
+
+// Accept either a full RFC 3339 timestamp or a plain date, meaning midnight UTC.
+fn parse_date(name: &str, date: &str) -> Result<DateTime<Utc>, ApiError> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(date) {
+        return Ok(date.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map(|date| date.and_hms_opt(0, 0, 0).unwrap().and_utc())
+        .map_err(|_| ApiError::BadRequest(format!("{name} must be a date (YYYY-MM-DD) or an RFC 3339 timestamp")))
+}
+
+impl ListQuery {
+    /// Does a post pass the author, tag and date filters?
+    pub fn matches(&self, post: &BlogPost) -> bool {
+        self.author.as_ref().is_none_or(|author| post.author.eq_ignore_ascii_case(author))
+            && self.tag.as_ref().is_none_or(|tag| post.tags.contains(tag))
+            && self.since.is_none_or(|since| post.date >= since)
+            && self.until.is_none_or(|until| post.date < until)
+    }
+
+    /// Compare two posts in the requested order.
+    pub fn compare(&self, a: &BlogPost, b: &BlogPost) -> Ordering {
+        let ordering = match self.sort {
+            SortField::Id => a.id.cmp(&b.id),
+            SortField::Date => a.date.cmp(&b.date).then(a.id.cmp(&b.id)),
+            SortField::Title => a.title.cmp(&b.title).then(a.id.cmp(&b.id)),
+        };
+        match self.order {
+            SortOrder::Asc => ordering,
+            SortOrder::Desc => ordering.reverse(),
+        }
+    }
+
+    /// Does a post come after the cursor?
+    pub fn is_after_cursor(&self, post: &BlogPost) -> bool {
+        let Some(cursor) = &self.after else {
+            return true;
+        };
+        let ordering = match self.sort {
+            SortField::Id => post.id.cmp(&cursor.id),
+            SortField::Date => compare_key(&post.date, cursor.date.as_ref()).then(post.id.cmp(&cursor.id)),
+            SortField::Title => compare_key(&post.title, cursor.title.as_ref()).then(post.id.cmp(&cursor.id)),
+        };
+        match self.order {
+            SortOrder::Asc => ordering.is_gt(),
+            SortOrder::Desc => ordering.is_lt(),
+        }
+    }
+
+    /// The cursor to pass back to get the page following `post`.
+    pub fn cursor_after(&self, post: &BlogPost) -> String {
+        Cursor {
+            sort: self.sort,
+            order: self.order,
+            date: (self.sort == SortField::Date).then_some(post.date),
+            title: (self.sort == SortField::Title).then(|| post.title.clone()),
+            id: post.id,
+        }
+        .encode()
+    }
+}
+
+// Compare a post's sort key with a cursor's. Posts always have one, so they
+// sort before a cursor without one.
+fn compare_key<T: Ord>(key: &T, cursor_key: Option<&T>) -> Ordering {
+    match cursor_key {
+        Some(cursor_key) => key.cmp(cursor_key),
+        None => Ordering::Less,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::StatusCode;
+    use axum::response::IntoResponse;
+
+    use super::*;
+
+    fn post(id: i32, day: u32, title: &str) -> BlogPost {
+        BlogPost {
+            id,
+            date: NaiveDate::from_ymd_opt(2023, 9, day).unwrap().and_hms_opt(12, 0, 0).unwrap().and_utc(),
+            title: title.to_string(),
+            body: String::new(),
+            author: "herbert".to_string(),
+            tags: vec![],
+            version: BlogPost::FIRST_VERSION,
+        }
+    }
+
+    // Several posts share a date, and a title
+    fn posts() -> Vec<BlogPost> {
+        vec![
+            post(1, 2, "Whales"),
+            post(2, 1, "Ships"),
+            post(3, 2, "Gophers"),
+            post(4, 1, "Ships"),
+            post(5, 2, "Boats"),
+        ]
+    }
+
+    fn query(sort: SortField, order: SortOrder, limit: usize, cursor: Option<String>) -> Result<ListQuery, ApiError> {
+        ListParams {
+            limit: Some(limit),
+            cursor,
+            sort: Some(sort),
+            order: Some(order),
+            ..ListParams::default()
+        }
+        .try_into()
+    }
+
+    // One page of `posts`, the way the stores make them: the IDs on it, and
+    // the cursor for the next page
+    fn page(posts: &[BlogPost], query: &ListQuery) -> (Vec<i32>, Option<String>) {
+        let mut matches: Vec<&BlogPost> = posts.iter().filter(|post| query.is_after_cursor(post)).collect();
+        matches.sort_by(|a, b| query.compare(a, b));
+        let page: Vec<&BlogPost> = matches.iter().copied().take(query.limit).collect();
+        let next = match matches.len() > query.limit {
+            true => page.last().map(|post| query.cursor_after(post)),
+            false => None,
+        };
+        (page.iter().map(|post| post.id).collect(), next)
+    }
+
+    // Every page, following the cursors to the end
+    fn pages(posts: &[BlogPost], sort: SortField, order: SortOrder, limit: usize) -> Vec<Vec<i32>> {
+        let mut pages = vec![];
+        let mut cursor = None;
+        loop {
+            let (ids, next) = page(posts, &query(sort, order, limit, cursor).unwrap());
+            pages.push(ids);
+            match next {
+                Some(next) => cursor = Some(next),
+                None => return pages,
+            }
+        }
+    }
+
+    fn status(error: ApiError) -> StatusCode {
+        error.into_response().status()
+    }
+
+    #[test]
+    fn an_empty_page_has_no_next_cursor() {
+        for sort in [SortField::Id, SortField::Date, SortField::Title] {
+            assert_eq!(pages(&[], sort, SortOrder::Asc, 2), [[] as [i32; 0]]);
+        }
+    }
+
+    #[test]
+    fn posts_on_the_same_date_are_ordered_by_id() {
+        assert_eq!(pages(&posts(), SortField::Date, SortOrder::Asc, 2), [vec![2, 4], vec![1, 3], vec![5]]);
+        assert_eq!(pages(&posts(), SortField::Date, SortOrder::Desc, 2), [vec![5, 3], vec![1, 4], vec![2]]);
+        // The cursor sits between two posts with the same date
+        assert_eq!(pages(&posts(), SortField::Date, SortOrder::Asc, 1), [[2], [4], [1], [3], [5]]);
+        assert_eq!(pages(&posts(), SortField::Date, SortOrder::Desc, 3), [vec![5, 3, 1], vec![4, 2]]);
+    }
+
+    #[test]
+    fn posts_with_the_same_title_are_ordered_by_id() {
+        assert_eq!(pages(&posts(), SortField::Title, SortOrder::Asc, 2), [vec![5, 3], vec![2, 4], vec![1]]);
+        assert_eq!(pages(&posts(), SortField::Title, SortOrder::Desc, 2), [vec![1, 4], vec![2, 3], vec![5]]);
+    }
+
+    #[test]
+    fn cursors_without_their_key_sort_last() {
+        for sort in [SortField::Date, SortField::Title] {
+            for order in [SortOrder::Asc, SortOrder::Desc] {
+                let mut query = query(sort, order, 3, None).unwrap();
+                let first_page = page(&posts(), &query).0;
+                query.after = Some(Cursor {
+                    sort,
+                    order,
+                    date: None,
+                    title: None,
+                    id: 3,
+                });
+                let expected = match order {
+                    SortOrder::Asc => vec![],
+                    SortOrder::Desc => first_page,
+                };
+                assert_eq!(page(&posts(), &query).0, expected, "{sort:?} {order:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn the_last_page_has_no_next_cursor() {
+        // Exactly filling the last page doesn't leave an empty one after it
+        assert_eq!(pages(&posts(), SortField::Id, SortOrder::Asc, 5), [[1, 2, 3, 4, 5]]);
+        assert_eq!(pages(&posts(), SortField::Id, SortOrder::Desc, 10), [[5, 4, 3, 2, 1]]);
+        assert_eq!(pages(&posts(), SortField::Id, SortOrder::Asc, 4), [vec![1, 2, 3, 4], vec![5]]);
+
+        // A cursor past the end gives an empty page
+        let last = posts().into_iter().max_by_key(|post| post.id).unwrap();
+        let cursor = query(SortField::Id, SortOrder::Asc, 2, None).unwrap().cursor_after(&last);
+        let query = query(SortField::Id, SortOrder::Asc, 2, Some(cursor)).unwrap();
+        assert_eq!(page(&posts(), &query), (vec![], None));
+    }
+
+    #[test]
+    fn malformed_cursors_are_bad_requests() {
+        let date_cursor = query(SortField::Date, SortOrder::Asc, 2, None).unwrap().cursor_after(&posts()[0]);
+        let not_a_cursor = URL_SAFE_NO_PAD.encode(br#"{"hello":"there"}"#);
+        for cursor in ["", "not base64!", "bm90IGpzb24", not_a_cursor.as_str(), &date_cursor[1..]] {
+            let error = query(SortField::Date, SortOrder::Asc, 2, Some(cursor.to_string())).unwrap_err();
+            assert_eq!(status(error), StatusCode::BAD_REQUEST, "{cursor:?}");
+        }
+
+        // A good cursor, but from another ordering
+        for (sort, order) in [(SortField::Date, SortOrder::Desc), (SortField::Id, SortOrder::Asc)] {
+            let error = query(sort, order, 2, Some(date_cursor.clone())).unwrap_err();
+            assert_eq!(status(error), StatusCode::BAD_REQUEST, "{sort:?} {order:?}");
+        }
+        assert!(query(SortField::Date, SortOrder::Asc, 2, Some(date_cursor)).is_ok());
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use chrono::{DateTime, Duration, Utc};
+
+// How often, in seconds, to forget usernames and addresses that have gone
+// quiet
+const SWEEP_INTERVAL: i64 = 60;
+
+/// When failed logins lock a username or an address out.
+#[derive(Clone, Copy, Debug)]
+pub struct LockoutPolicy {
+    /// How many failures lock it out. Zero turns lockouts off.
+    pub max_failures: u32,
+    /// How long failures count towards a lockout.
+    pub window: Duration,
+    /// How long the first lockout lasts. Each one after it lasts twice as
+    /// long as the one before.
+    pub lockout: Duration,
+    /// The longest a lockout lasts.
+    pub max_lockout: Duration,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Subject {
+    User(String),
+    Ip(IpAddr),
+}
+
+// The failures of one username or address
+struct Record {
+    // Failures since `window_start`
+    failures: u32,
+    window_start: DateTime<Utc>,
+    // How many lockouts there have been since it was last quiet, which sets
+    // how long the next one lasts
+    lockouts: u32,
+    locked_until: Option<DateTime<Utc>>,
+    last_failure: DateTime<Utc>,
+}
+
+/// Failed logins per username and per address, and the lockouts they've
+/// earned.
+pub struct Lockouts {
+    policy: LockoutPolicy,
+    records: Mutex<Records>,
+}
+
+struct Records {
+    by_subject: HashMap<Subject, Record>,
+    last_sweep: DateTime<Utc>,
+}
+
+impl Lockouts {
+    pub fn new(policy: LockoutPolicy) -> Self {
+        Self {
+            policy,
+            records: Mutex::new(Records {
+                by_subject: HashMap::new(),
+                last_sweep: Utc::now(),
+            }),
+        }
+    }
+
+    /// When the username or the address is locked out until, if either is.
+    pub fn locked_until(&self, username: &str, ip: IpAddr) -> Option<DateTime<Utc>> {
+        let now = Utc::now();
+        let records = self.lock();
+        [Subject::User(username.to_string()), Subject::Ip(ip)]
+            .iter()
+            .filter_map(|subject| records.by_subject.get(subject)?.locked_until)
+            .filter(|until| *until > now)
+            .max()
+    }
+
+    /// Count a failed login against the username and the address. Returns
+    /// when they're locked out until, if this locked either of them out.
+    pub fn record_failure(&self, username: &str, ip: IpAddr) -> Option<DateTime<Utc>> {
+        if self.policy.max_failures == 0 {
+            return None;
+        }
+
+        let now = Utc::now();
+        let mut records = self.lock();
+        if (now - records.last_sweep).num_seconds() >= SWEEP_INTERVAL {
+            self.sweep(&mut records, now);
+        }
+
+        let mut locked_until = None;
+        for subject in [Subject::User(username.to_string()), Subject::Ip(ip)] {
+            let record = records.by_subject.entry(subject.clone()).or_insert(Record {
+                failures: 0,
+                window_start: now,
+                lockouts: 0,
+                locked_until: None,
+                last_failure: now,
+            });
+            if let Some(until) = self.fail(record, now) {
+                tracing::warn!(?subject, %until, "Locked out after too many failed logins");
+                locked_until = locked_until.max(Some(until));
+            }
+        }
+        locked_until
+    }
+
+    /// Forget a username's failures once it logs in. The address's are kept,
+    /// so logging in to one account can't be used to keep guessing at others.
+    pub fn record_success(&self, username: &str) {
+        self.lock().by_subject.remove(&Subject::User(username.to_string()));
+    }
+
+    /// Lift a username's lockout and forget its failures. Returns whether
+    /// there was anything to forget.
+    pub fn unlock_user(&self, username: &str) -> bool {
+        self.unlock(Subject::User(username.to_string()))
+    }
+
+    /// Lift an address's lockout and forget its failures. Returns whether
+    /// there was anything to forget.
+    pub fn unlock_ip(&self, ip: IpAddr) -> bool {
+        self.unlock(Subject::Ip(ip))
+    }
+
+    fn unlock(&self, subject: Subject) -> bool {
+        let removed = self.lock().by_subject.remove(&subject).is_some();
+        if removed {
+            tracing::info!(?subject, "Unlocked logins");
+        }
+        removed
+    }
+
+    // Count a failure, and lock the subject out if it's had too many.
+    // Returns when it's locked out until, if it now is.
+    fn fail(&self, record: &mut Record, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        if now - record.window_start >= self.policy.window {
+            record.failures = 0;
+            record.window_start = now;
+        }
+        record.failures += 1;
+        record.last_failure = now;
+        if record.failures < self.policy.max_failures {
+            return None;
+        }
+
+        record.failures = 0;
+        record.window_start = now;
+        record.lockouts += 1;
+        // A lockout too long to add to the time lasts as long as there is
+        let until = now
+            .checked_add_signed(self.lockout_length(record.lockouts))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC);
+        record.locked_until = Some(until);
+        Some(until)
+    }
+
+    // How long the nth lockout in a row lasts: twice as long as the one
+    // before it, up to the longest allowed
+    fn lockout_length(&self, lockouts: u32) -> Duration {
+        let doublings = 2i64.saturating_pow(lockouts.saturating_sub(1));
+        let seconds = self.policy.lockout.num_seconds().saturating_mul(doublings);
+        match Duration::try_seconds(seconds) {
+            Some(length) => length.min(self.policy.max_lockout),
+            None => self.policy.max_lockout,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Records> {
+        // Every change to the records is whole before anything that could
+        // panic, so carry on after a panic elsewhere
+        self.records.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // A subject that's no longer locked out, and has had no failures for the
+    // failure window or the longest lockout, whichever is longer, starts
+    // again from nothing
+    fn sweep(&self, records: &mut Records, now: DateTime<Utc>) {
+        let quiet = self.policy.window.max(self.policy.max_lockout);
+        records.by_subject.retain(|_, record| {
+            record.locked_until.is_some_and(|until| until > now) || now - record.last_failure < quiet
+        });
+        records.last_sweep = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+
+    fn lockouts(lockout: Duration, max_lockout: Duration) -> Lockouts {
+        Lockouts::new(LockoutPolicy {
+            max_failures: 1,
+            window: Duration::minutes(15),
+            lockout,
+            max_lockout,
+        })
+    }
+
+    // Lockouts after `max_failures` failures, lasting a minute
+    fn allowing(max_failures: u32) -> Lockouts {
+        Lockouts::new(LockoutPolicy {
+            max_failures,
+            window: Duration::minutes(15),
+            lockout: Duration::minutes(1),
+            max_lockout: Duration::hours(1),
+        })
+    }
+
+    fn ip(last: u8) -> IpAddr {
+        IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, last))
+    }
+
+    #[test]
+    fn too_many_failures_lock_out() {
+        let lockouts = allowing(3);
+        assert_eq!(lockouts.record_failure("herbert", IP), None);
+        assert_eq!(lockouts.record_failure("herbert", IP), None);
+        assert_eq!(lockouts.locked_until("herbert", IP), None);
+
+        let until = lockouts.record_failure("herbert", IP).expect("the third failure locks out");
+        let minute = until - Utc::now();
+        assert!(minute > Duration::seconds(55) && minute <= Duration::minutes(1), "{minute}");
+        assert_eq!(lockouts.locked_until("herbert", IP), Some(until));
+    }
+
+    #[test]
+    fn failures_outside_the_window_dont_count() {
+        let lockouts = allowing(2);
+        let start = Utc::now();
+        let mut record = Record {
+            failures: 0,
+            window_start: start,
+            lockouts: 0,
+            locked_until: None,
+            last_failure: start,
+        };
+        assert_eq!(lockouts.fail(&mut record, start), None);
+        let later = start + lockouts.policy.window;
+        assert_eq!(lockouts.fail(&mut record, later), None);
+        assert_eq!(record.failures, 1);
+        assert!(lockouts.fail(&mut record, later + Duration::seconds(1)).is_some());
+    }
+
+    #[test]
+    fn logging_in_forgets_the_usernames_failures() {
+        let lockouts = allowing(3);
+        lockouts.record_failure("herbert", ip(1));
+        lockouts.record_failure("herbert", ip(1));
+        lockouts.record_success("herbert");
+        lockouts.record_failure("herbert", ip(2));
+        lockouts.record_failure("herbert", ip(2));
+        assert_eq!(lockouts.locked_until("herbert", ip(3)), None);
+    }
+
+    #[test]
+    fn usernames_and_addresses_lock_out_separately() {
+        let lockouts = allowing(2);
+        lockouts.record_failure("herbert", ip(1));
+        assert!(lockouts.record_failure("ashley", ip(1)).is_some());
+        // Everyone's locked out from the address that guessed too often...
+        assert!(lockouts.locked_until("gladys", ip(1)).is_some());
+        // ...but not from anywhere else
+        assert_eq!(lockouts.locked_until("herbert", ip(2)), None);
+        assert_eq!(lockouts.locked_until("ashley", ip(2)), None);
+
+        assert!(lockouts.record_failure("herbert", ip(2)).is_some());
+        // The username that was guessed at too often is locked out everywhere
+        assert!(lockouts.locked_until("herbert", ip(3)).is_some());
+        assert_eq!(lockouts.locked_until("ashley", ip(3)), None);
+    }
+
+    #[test]
+    fn unlocking_lifts_the_lockout() {
+        let lockouts = allowing(1);
+        assert!(lockouts.record_failure("herbert", IP).is_some());
+
+        assert!(lockouts.unlock_user("herbert"));
+        assert!(!lockouts.unlock_user("herbert"));
+        assert_eq!(lockouts.locked_until("herbert", ip(1)), None);
+        assert!(lockouts.locked_until("herbert", IP).is_some());
+
+        assert!(lockouts.unlock_ip(IP));
+        assert_eq!(lockouts.locked_until("herbert", IP), None);
+    }
+
+    #[test]
+    fn lockouts_double_up_to_the_longest() {
+        let lockouts = lockouts(Duration::seconds(60), Duration::seconds(200));
+        let lengths: Vec<_> = (1..=5).map(|n| lockouts.lockout_length(n).num_seconds()).collect();
+        assert_eq!(lengths, [60, 120, 200, 200, 200]);
+    }
+
+    #[test]
+    fn huge_lockouts_dont_overflow() {
+        let lockouts = lockouts(Duration::max_value(), Duration::max_value());
+        assert_eq!(lockouts.lockout_length(40), Duration::max_value());
+        for _ in 0..3 {
+            assert_eq!(lockouts.record_failure("herbert", IP), Some(DateTime::<Utc>::MAX_UTC));
+        }
+        assert_eq!(lockouts.locked_until("herbert", IP), Some(DateTime::<Utc>::MAX_UTC));
+    }
+
+    #[test]
+    fn a_panic_while_locked_doesnt_stop_logins() {
+        let lockouts = lockouts(Duration::seconds(60), Duration::seconds(60));
+        let _ = std::panic::catch_unwind(|| {
+            let _records = lockouts.lock();
+            panic!("while holding the lock");
+        });
+        assert!(lockouts.records.is_poisoned());
+        assert!(lockouts.record_failure("herbert", IP).is_some());
+        assert!(lockouts.unlock_user("herbert"));
+    }
+}
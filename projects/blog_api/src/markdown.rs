@@ -0,0 +1,197 @@
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Mutex, OnceLock, PoisonError};
+
+use ammonia::Builder;
+use blog_model::BlogPost;
+use pulldown_cmark::{html, Options, Parser};
+
+// Footnotes link to element IDs, which could otherwise clash with (or
+// clobber) the page around the post. Both ends get this prefix.
+const ID_PREFIX: &str = "post-";
+
+/// Render a post body written in CommonMark (with tables, footnotes and
+/// strikethrough) to HTML that is safe to put in a page. Raw HTML in the body
+/// is allowed, but scripts, event handlers, `javascript:` links and the like
+/// are removed.
+pub fn render_markdown(body: &str) -> String {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_TABLES);
+    options.insert(Options::ENABLE_FOOTNOTES);
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+
+    let mut unsafe_html = String::with_capacity(body.len() * 3 / 2);
+    html::push_html(&mut unsafe_html, Parser::new_ext(body, options));
+    sanitizer().clean(&unsafe_html).to_string()
+}
+
+// Ammonia's defaults, plus the few attributes the Markdown renderer relies on.
+fn sanitizer() -> &'static Builder<'static> {
+    static SANITIZER: OnceLock<Builder<'static>> = OnceLock::new();
+    SANITIZER.get_or_init(|| {
+        let mut builder = Builder::default();
+        builder
+            .add_tag_attributes("code", &["class"])
+            .add_tag_attributes("div", &["class", "id"])
+            .add_tag_attributes("sup", &["class"])
+            .add_tag_attributes("th", &["style"])
+            .add_tag_attributes("td", &["style"])
+            .id_prefix(Some(ID_PREFIX))
+            .attribute_filter(|element, attribute, value| match (element, attribute) {
+                // The language of a fenced code block
+                ("code", "class") => value.starts_with("language-").then_some(Cow::Borrowed(value)),
+                ("div", "class") => (value == "footnote-definition").then_some(Cow::Borrowed(value)),
+                ("sup", "class") => ["footnote-reference", "footnote-definition-label"]
+                    .contains(&value)
+                    .then_some(Cow::Borrowed(value)),
+                // Table column alignment, and nothing else
+                ("th" | "td", "style") => value
+                    .strip_prefix("text-align: ")
+                    .is_some_and(|align| ["left", "center", "right"].contains(&align))
+                    .then_some(Cow::Borrowed(value)),
+                // Keep links within the page pointing at the prefixed IDs
+                ("a", "href") => match value.strip_prefix('#') {
+                    Some(id) => Some(Cow::Owned(format!("#{ID_PREFIX}{id}"))),
+                    None => Some(Cow::Borrowed(value)),
+                },
+                _ => Some(Cow::Borrowed(value)),
+            });
+        builder
+    })
+}
+
+/// Rendered post bodies, so each body is only rendered once. Entries are
+/// checked against a hash of the body, so a post edited behind the API's back
+/// still can't be served with its old HTML.
+#[derive(Default)]
+pub struct RenderCache {
+    // post ID -> (hash of the body, rendered HTML)
+    entries: Mutex<HashMap<i32, (u64, String)>>,
+}
+
+impl RenderCache {
+    /// The post's body as HTML, rendered now if it isn't cached.
+    pub fn html(&self, post: &BlogPost) -> String {
+        let mut hasher = DefaultHasher::new();
+        post.body.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        if let Some((cached_hash, html)) = self.lock().get(&post.id) {
+            if *cached_hash == hash {
+                return html.clone();
+            }
+        }
+
+        // Render without holding the lock; two requests may both render the
+        // same post, which is harmless
+        let html = render_markdown(&post.body);
+        self.lock().insert(post.id, (hash, html.clone()));
+        html
+    }
+
+    /// Forget a post's HTML, after it is edited or deleted.
+    pub fn invalidate(&self, id: i32) {
+        self.lock().remove(&id);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<i32, (u64, String)>> {
+        // A panic elsewhere can't leave the cache half-updated, so carry on
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(id: i32, body: &str) -> BlogPost {
+        BlogPost {
+            id,
+            date: chrono::Utc::now(),
+            title: "Whales".to_string(),
+            body: body.to_string(),
+            author: "herbert".to_string(),
+            tags: vec![],
+            version: BlogPost::FIRST_VERSION,
+        }
+    }
+
+    #[test]
+    fn scripts_are_stripped() {
+        let html = render_markdown("Hello\n\n<script>alert(1)</script>\n\n<p>there<script src=\"x.js\"></script></p>");
+        assert!(!html.contains("<script"), "{html}");
+        assert!(!html.contains("alert"), "{html}");
+        assert!(html.contains("Hello") && html.contains("there"), "{html}");
+    }
+
+    #[test]
+    fn javascript_links_lose_their_href() {
+        let html = render_markdown("[x](javascript:alert(1)) and <a href=\"JavaScript:alert(2)\">y</a>");
+        assert!(!html.to_lowercase().contains("javascript"), "{html}");
+        assert!(html.contains(">x</a>") && html.contains(">y</a>"), "{html}");
+
+        let html = render_markdown("[ok](https://example.com/)");
+        assert!(html.contains(r#"href="https://example.com/""#), "{html}");
+    }
+
+    #[test]
+    fn event_handlers_are_removed() {
+        let html = render_markdown(r#"<img src="whale.png" onerror="alert(1)"> <b onclick="alert(2)">bold</b>"#);
+        assert!(!html.contains("onerror") && !html.contains("onclick") && !html.contains("alert"), "{html}");
+        assert!(html.contains(r#"<img src="whale.png""#) && html.contains("<b>bold</b>"), "{html}");
+    }
+
+    #[test]
+    fn tables_code_and_footnotes_survive() {
+        let body = "\
+| Whale | Length |
+|:------|-------:|
+| Blue  | 30m    |
+
+```rust
+fn main() {}
+```
+
+Whales are big.[^size]
+
+[^size]: Very big.
+";
+        let html = render_markdown(body);
+        assert!(html.contains("<table>"), "{html}");
+        assert!(html.contains(r#"<th style="text-align: left">Whale</th>"#), "{html}");
+        assert!(html.contains(r#"<td style="text-align: right">30m</td>"#), "{html}");
+        assert!(html.contains(r#"<pre><code class="language-rust">fn main() {}"#), "{html}");
+        assert!(html.contains(r##"<a href="#post-size""##), "{html}");
+        assert!(html.contains(r#"<div class="footnote-definition" id="post-size">"#), "{html}");
+    }
+
+    #[test]
+    fn styles_and_classes_are_limited() {
+        let html = render_markdown(r#"<td style="color: red">x</td><code class="evil">y</code>"#);
+        assert!(!html.contains("color") && !html.contains("evil"), "{html}");
+    }
+
+    #[test]
+    fn edited_posts_are_rendered_again() {
+        let cache = RenderCache::default();
+        let mut whale = post(1, "A *big* whale");
+        assert_eq!(cache.html(&whale), "<p>A <em>big</em> whale</p>\n");
+        assert_eq!(cache.html(&whale), "<p>A <em>big</em> whale</p>\n");
+
+        // Even without being told, the cache notices the body has changed
+        whale.body = "A **huge** whale".to_string();
+        whale.version += 1;
+        assert_eq!(cache.html(&whale), "<p>A <strong>huge</strong> whale</p>\n");
+
+        // Other posts are kept apart
+        let ship = post(2, "A ship");
+        assert_eq!(cache.html(&ship), "<p>A ship</p>\n");
+        assert_eq!(cache.lock().len(), 2);
+
+        cache.invalidate(1);
+        assert_eq!(cache.lock().len(), 1);
+        assert_eq!(cache.html(&whale), "<p>A <strong>huge</strong> whale</p>\n");
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::extract::{MatchedPath, State};
+use axum::http::{header, Request};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+use crate::{ApiError, AppState};
+
+/// The upper bounds of the latency histograms' buckets, in seconds.
+pub const LATENCY_BUCKETS: [f64; 12] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+/// A Prometheus histogram of durations, bucketed by `LATENCY_BUCKETS`. It
+/// can be updated through a shared reference from any thread.
+#[derive(Default)]
+pub struct Histogram {
+    // How many observations fell in each bucket (not cumulative)
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+impl Histogram {
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|&bound| seconds <= bound) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos.fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+/// A response for `/metrics`, in the Prometheus text format, built up one
+/// metric at a time.
+#[derive(Default)]
+pub struct MetricsText(String);
+
+impl MetricsText {
+    /// Start a metric, with its help text and type (`counter`, `gauge` or
+    /// `histogram`). Its samples must follow before the next one starts.
+    pub fn metric(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.0, "# HELP {name} {help}");
+        let _ = writeln!(self.0, "# TYPE {name} {kind}");
+    }
+
+    /// One value of the current metric.
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl Display) {
+        self.0.push_str(name);
+        write_labels(&mut self.0, labels);
+        let _ = writeln!(self.0, " {value}");
+    }
+
+    /// A gauge with a single value.
+    pub fn gauge(&mut self, name: &str, help: &str, value: impl Display) {
+        self.metric(name, "gauge", help);
+        self.sample(name, &[], value);
+    }
+
+    /// The samples of one histogram of the current metric.
+    pub fn histogram(&mut self, name: &str, labels: &[(&str, &str)], histogram: &Histogram) {
+        let mut cumulative = 0;
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&histogram.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            let le = bound.to_string();
+            self.sample(&format!("{name}_bucket"), &[labels, &[("le", le.as_str())]].concat(), cumulative);
+        }
+        let count = histogram.count.load(Ordering::Relaxed);
+        self.sample(&format!("{name}_bucket"), &[labels, &[("le", "+Inf")]].concat(), count);
+        let sum = histogram.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+        self.sample(&format!("{name}_sum"), labels, sum);
+        self.sample(&format!("{name}_count"), labels, count);
+    }
+}
+
+fn write_labels(out: &mut String, labels: &[(&str, &str)]) {
+    if labels.is_empty() {
+        return;
+    }
+    out.push('{');
+    for (i, (name, value)) in labels.iter().enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        out.push_str(name);
+        out.push_str("=\"");
+        for c in value.chars() {
+            match c {
+                '\\' => out.push_str("\\\\"),
+                '"' => out.push_str("\\\""),
+                '\n' => out.push_str("\\n"),
+                c => out.push(c),
+            }
+        }
+        out.push('"');
+    }
+    out.push('}');
+}
+
+/// How many requests each route has answered, and how quickly, split up by
+/// method and status.
+#[derive(Default)]
+pub struct RequestMetrics {
+    // Keyed by (method, route, status)
+    by_route: Mutex<BTreeMap<(String, String, u16), Histogram>>,
+}
+
+impl RequestMetrics {
+    fn record(&self, method: &str, route: &str, status: u16, latency: Duration) {
+        let mut by_route = self.by_route.lock().unwrap();
+        let key = (method.to_string(), route.to_string(), status);
+        by_route.entry(key).or_default().observe(latency);
+    }
+
+    fn write(&self, out: &mut MetricsText) {
+        let by_route = self.by_route.lock().unwrap();
+
+        out.metric("blog_http_requests_total", "counter", "Requests answered, by method, route and status.");
+        for ((method, route, status), histogram) in by_route.iter() {
+            let status = status.to_string();
+            let labels = [("method", method.as_str()), ("route", route.as_str()), ("status", status.as_str())];
+            out.sample("blog_http_requests_total", &labels, histogram.count.load(Ordering::Relaxed));
+        }
+
+        out.metric(
+            "blog_http_request_duration_seconds",
+            "histogram",
+            "How long requests took to answer, by method, route and status.",
+        );
+        for ((method, route, status), histogram) in by_route.iter() {
+            let status = status.to_string();
+            let labels = [("method", method.as_str()), ("route", route.as_str()), ("status", status.as_str())];
+            out.histogram("blog_http_request_duration_seconds", &labels, histogram);
+        }
+    }
+}
+
+// Middleware that times every request for `RequestMetrics`. Routes are
+// labelled by their pattern, e.g. `/blog/:id`, so each post doesn't get its
+// own series; requests that match no route share one.
+pub(crate) async fn track_requests<B>(State(state): State<AppState>, request: Request<B>, next: Next<B>) -> Response {
+    let started = Instant::now();
+    let method = request.method().to_string();
+    let route = match request.extensions().get::<MatchedPath>() {
+        Some(path) => path.as_str().to_string(),
+        None => "unmatched".to_string(),
+    };
+    let response = next.run(request).await;
+    state.requests.record(&method, &route, response.status().as_u16(), started.elapsed());
+    response
+}
+
+// Everything there is to measure, in the Prometheus text format
+pub(crate) async fn metrics(State(state): State<AppState>) -> Result<Response, ApiError> {
+    let mut out = MetricsText::default();
+    state.requests.write(&mut out);
+    state.store.write_metrics(&mut out).await?;
+    write_process_metrics(&mut out);
+    Ok(([(header::CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")], out.0).into_response())
+}
+
+// The standard process metrics, as other Prometheus clients name them. Linux
+// is the only system they're read on.
+#[cfg(target_os = "linux")]
+fn write_process_metrics(out: &mut MetricsText) {
+    // Clock ticks per second in /proc. Linux fixes this at 100 for userspace.
+    const TICKS_PER_SECOND: f64 = 100.0;
+
+    // /proc/self/stat's fields come after the command name, which is in
+    // brackets and can itself contain spaces
+    let stat = std::fs::read_to_string("/proc/self/stat").unwrap_or_default();
+    let fields: Vec<&str> = stat.rsplit_once(')').map_or(vec![], |(_, rest)| rest.split_whitespace().collect());
+    let field = |n: usize| fields.get(n - 3).and_then(|value| value.parse::<f64>().ok());
+
+    if let (Some(user), Some(system)) = (field(14), field(15)) {
+        out.metric("process_cpu_seconds_total", "counter", "CPU time used by the process, in seconds.");
+        out.sample("process_cpu_seconds_total", &[], (user + system) / TICKS_PER_SECOND);
+    }
+    if let Some(threads) = field(20) {
+        out.gauge("process_threads", "Threads in the process.", threads);
+    }
+    if let Some(virtual_bytes) = field(23) {
+        out.gauge("process_virtual_memory_bytes", "Virtual memory size, in bytes.", virtual_bytes);
+    }
+
+    // Resident memory is in pages in stat, so read it in kB from status
+    let status = std::fs::read_to_string("/proc/self/status").unwrap_or_default();
+    let resident_kb = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))
+        .and_then(|value| value.trim().trim_end_matches("kB").trim().parse::<u64>().ok());
+    if let Some(kb) = resident_kb {
+        out.gauge("process_resident_memory_bytes", "Resident memory size, in bytes.", kb * 1024);
+    }
+
+    // Start time is in ticks since boot, and boot time is in /proc/stat
+    let boot_time = std::fs::read_to_string("/proc/stat").ok().and_then(|stat| {
+        stat.lines()
+            .find_map(|line| line.strip_prefix("btime "))
+            .and_then(|value| value.trim().parse::<f64>().ok())
+    });
+    if let (Some(boot_time), Some(start_ticks)) = (boot_time, field(22)) {
+        out.gauge(
+            "process_start_time_seconds",
+            "When the process started, in seconds since the Unix epoch.",
+            boot_time + start_ticks / TICKS_PER_SECOND,
+        );
+    }
+
+    if let Ok(fds) = std::fs::read_dir("/proc/self/fd") {
+        out.gauge("process_open_fds", "Open file descriptors.", fds.count());
+    }
+    let max_fds = std::fs::read_to_string("/proc/self/limits").ok().and_then(|limits| {
+        limits
+            .lines()
+            .find_map(|line| line.strip_prefix("Max open files"))
+            .and_then(|values| values.split_whitespace().next()?.parse::<u64>().ok())
+    });
+    if let Some(max_fds) = max_fds {
+        out.gauge("process_max_fds", "The most file descriptors the process may open.", max_fds);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn write_process_metrics(_out: &mut MetricsText) {}
@@ -0,0 +1,190 @@
+use askama::Template;
+use axum::extract::State;
+use axum::http::{header, StatusCode, Uri};
+use axum::response::{Html, IntoResponse, Response};
+use blog_model::{BlogPost, Comment, CommentThread};
+use serde::Deserialize;
+
+use crate::{ApiError, ApiPath, ApiQuery, AppState, Conditions, ListParams, SortField, SortOrder, Validators};
+
+// How many posts the index and author pages show at a time
+const PAGE_SIZE: usize = 10;
+
+const STYLESHEET: &str = include_str!("../static/style.css");
+
+// A template, rendered into an HTML response
+struct Page<T>(T);
+
+impl<T: Template> IntoResponse for Page<T> {
+    fn into_response(self) -> Response {
+        match self.0.render() {
+            Ok(html) => Html(html).into_response(),
+            Err(e) => {
+                tracing::error!("Unable to render a page: {e}");
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    }
+}
+
+// A post with its body rendered from Markdown
+struct PostView {
+    post: BlogPost,
+    body_html: String,
+}
+
+#[derive(Template)]
+#[template(path = "index.html")]
+struct IndexTemplate {
+    posts: Vec<PostView>,
+    base_url: String,
+    first_page: bool,
+    next_cursor: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "author.html")]
+struct AuthorTemplate {
+    author: String,
+    posts: Vec<PostView>,
+    base_url: String,
+    first_page: bool,
+    next_cursor: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "post.html")]
+struct PostTemplate {
+    post: BlogPost,
+    body_html: String,
+    // Comments in reading order, each with how deeply it's nested
+    comments: Vec<(usize, Comment)>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct PageParams {
+    cursor: Option<String>,
+}
+
+// Posts are shown newest first, a page at a time
+async fn newest_posts(
+    state: &AppState,
+    author: Option<String>,
+    cursor: Option<String>,
+) -> Result<(Vec<PostView>, Option<String>), ApiError> {
+    let query = ListParams {
+        limit: Some(PAGE_SIZE),
+        cursor,
+        sort: Some(SortField::Date),
+        order: Some(SortOrder::Desc),
+        author,
+        ..ListParams::default()
+    }
+    .try_into()?;
+    let page = state.store.list(&query).await?;
+    let posts = page
+        .posts
+        .into_iter()
+        .map(|post| PostView {
+            body_html: state.rendered.html(&post),
+            post,
+        })
+        .collect();
+    Ok((posts, page.next_cursor))
+}
+
+// The front page: the latest posts
+pub(crate) async fn index_page(
+    State(state): State<AppState>,
+    conditions: Conditions,
+    uri: Uri,
+    ApiQuery(params): ApiQuery<PageParams>,
+) -> Result<Response, ApiError> {
+    let validators = Validators::for_store(state.store.version().await?, &uri);
+    if conditions.is_fresh(&validators) {
+        return Ok(validators.not_modified());
+    }
+
+    let first_page = params.cursor.is_none();
+    let (posts, next_cursor) = newest_posts(&state, None, params.cursor).await?;
+    Ok(validators.attach(Page(IndexTemplate {
+        posts,
+        base_url: "/".to_string(),
+        first_page,
+        next_cursor,
+    })))
+}
+
+// Everything one author has written
+pub(crate) async fn author_page(
+    State(state): State<AppState>,
+    conditions: Conditions,
+    uri: Uri,
+    ApiPath(author): ApiPath<String>,
+    ApiQuery(params): ApiQuery<PageParams>,
+) -> Result<Response, ApiError> {
+    let validators = Validators::for_store(state.store.version().await?, &uri);
+    if conditions.is_fresh(&validators) {
+        return Ok(validators.not_modified());
+    }
+
+    let first_page = params.cursor.is_none();
+    let (posts, next_cursor) = newest_posts(&state, Some(author.clone()), params.cursor).await?;
+    Ok(validators.attach(Page(AuthorTemplate {
+        base_url: format!("/authors/{}", encode_path_segment(&author)),
+        author,
+        posts,
+        first_page,
+        next_cursor,
+    })))
+}
+
+// A single post, with its comments
+pub(crate) async fn post_page(
+    State(state): State<AppState>,
+    conditions: Conditions,
+    uri: Uri,
+    ApiPath(id): ApiPath<i32>,
+) -> Result<Response, ApiError> {
+    let validators = Validators::for_store(state.store.version().await?, &uri);
+    if conditions.is_fresh(&validators) {
+        return Ok(validators.not_modified());
+    }
+
+    let post = state.store.get(id).await?.ok_or(ApiError::NotFound)?;
+    let comments = state.store.comments(id).await?.unwrap_or_default();
+
+    let mut flattened = Vec::new();
+    flatten(CommentThread::build(comments), 0, &mut flattened);
+    Ok(validators.attach(Page(PostTemplate {
+        body_html: state.rendered.html(&post),
+        post,
+        comments: flattened,
+    })))
+}
+
+// The stylesheet every page links to
+pub(crate) async fn stylesheet() -> impl IntoResponse {
+    ([(header::CONTENT_TYPE, "text/css; charset=utf-8")], STYLESHEET)
+}
+
+// Walk comment threads depth first, so replies follow what they reply to
+fn flatten(threads: Vec<CommentThread>, depth: usize, out: &mut Vec<(usize, Comment)>) {
+    for thread in threads {
+        out.push((depth, thread.comment));
+        flatten(thread.replies, depth + 1, out);
+    }
+}
+
+// Percent-encode everything but unreserved characters, for use in a URL path
+fn encode_path_segment(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
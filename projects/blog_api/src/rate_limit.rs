@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::extract::{ConnectInfo, State};
+use axum::http::{Method, Request};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+use crate::ApiError;
+
+// How often to forget clients that have gone quiet
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How many requests a client may make: `burst` at once, refilled at `rate`
+/// per second. A rate of zero means no limit.
+#[derive(Clone, Copy, Debug)]
+pub struct Budget {
+    pub rate: f64,
+    pub burst: u32,
+}
+
+/// Who a rate limit applies to. Middleware that authenticates a client can
+/// put one of these in the request's extensions, so that the client has one
+/// budget wherever it connects from; otherwise clients are told apart by IP.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RateLimitKey(pub String);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Kind {
+    Read,
+    Write,
+}
+
+#[derive(PartialEq, Eq, Hash)]
+enum Client {
+    Ip(IpAddr),
+    Key(RateLimitKey),
+}
+
+// A token bucket. It starts full, each request takes a token, and tokens
+// come back at the budget's rate.
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token-bucket rate limits per client, with separate budgets for reads
+/// (GET, HEAD and OPTIONS) and for everything else.
+pub struct RateLimiter {
+    read: Budget,
+    write: Budget,
+    buckets: Mutex<Buckets>,
+}
+
+struct Buckets {
+    by_client: HashMap<(Client, Kind), Bucket>,
+    last_sweep: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(read: Budget, write: Budget) -> Self {
+        Self {
+            read,
+            write,
+            buckets: Mutex::new(Buckets {
+                by_client: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+        }
+    }
+
+    fn budget(&self, kind: Kind) -> Budget {
+        match kind {
+            Kind::Read => self.read,
+            Kind::Write => self.write,
+        }
+    }
+
+    // Take a token from the client's bucket, or say how many seconds until
+    // there'll be one
+    fn take(&self, client: Client, kind: Kind) -> Result<(), u64> {
+        let budget = self.budget(kind);
+        if budget.rate <= 0.0 {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if now.duration_since(buckets.last_sweep) >= SWEEP_INTERVAL {
+            self.sweep(&mut buckets, now);
+        }
+
+        let bucket = buckets.by_client.entry((client, kind)).or_insert(Bucket {
+            tokens: budget.burst as f64,
+            updated: now,
+        });
+        bucket.tokens = refill(bucket, budget, now);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / budget.rate).ceil() as u64)
+        }
+    }
+
+    // A bucket that would have refilled by now is no different from a new
+    // one, so it can go
+    fn sweep(&self, buckets: &mut Buckets, now: Instant) {
+        buckets.by_client.retain(|(_, kind), bucket| {
+            let budget = self.budget(*kind);
+            refill(bucket, budget, now) < budget.burst as f64
+        });
+        buckets.last_sweep = now;
+    }
+}
+
+// How many tokens a bucket has now, counting those that came back since it
+// was last used
+fn refill(bucket: &Bucket, budget: Budget, now: Instant) -> f64 {
+    let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+    (bucket.tokens + elapsed * budget.rate).min(budget.burst as f64)
+}
+
+// Middleware that turns clients away with a 429 once they've used up their
+// budget. Clients are told apart by the address they connect from, which
+// needs the server started with `into_make_service_with_connect_info`.
+pub(crate) async fn rate_limit<B>(
+    State(limiter): State<Arc<RateLimiter>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let kind = match *request.method() {
+        Method::GET | Method::HEAD | Method::OPTIONS => Kind::Read,
+        _ => Kind::Write,
+    };
+    let client = match request.extensions().get::<RateLimitKey>() {
+        Some(key) => Client::Key(key.clone()),
+        None => {
+            let addr = request.extensions().get::<ConnectInfo<SocketAddr>>();
+            Client::Ip(addr.map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |ConnectInfo(addr)| addr.ip()))
+        }
+    };
+
+    match limiter.take(client, kind) {
+        Ok(()) => next.run(request).await,
+        Err(retry_after) => ApiError::TooManyRequests(retry_after).into_response(),
+    }
+}
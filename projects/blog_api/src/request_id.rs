@@ -0,0 +1,70 @@
+use std::time::Duration;
+
+use axum::http::{HeaderValue, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use tracing::Span;
+
+/// The header used to pass a request ID in from a proxy, and to hand it back
+/// to the client.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Middleware that gives every request an ID. If the client (or a proxy in
+/// front of us) already supplied one in `x-request-id` we keep it, otherwise
+/// we make a new one. The ID is echoed back in the response headers, and
+/// added to the request's so that later layers can log it.
+///
+/// Install it with `axum::middleware::from_fn(blog_api::request_id)`.
+pub async fn request_id<B>(mut request: Request<B>, next: Next<B>) -> Response {
+    let id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty())
+        .map(|value| value.to_string())
+        .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    let value = HeaderValue::from_str(&id).ok();
+    if let Some(value) = &value {
+        request.headers_mut().insert(REQUEST_ID_HEADER, value.clone());
+    }
+
+    let mut response = REQUEST_ID.scope(id, next.run(request)).await;
+    if let Some(value) = value {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+// The span a request is handled in, so everything logged while handling it
+// can be traced back to it. It has to run inside `request_id`, which makes
+// sure there's an ID in the headers.
+pub(crate) fn request_span<B>(request: &Request<B>) -> Span {
+    let id = request.headers().get(REQUEST_ID_HEADER).and_then(|value| value.to_str().ok());
+    tracing::info_span!(
+        "request",
+        method = %request.method(),
+        path = %request.uri().path(),
+        request_id = id.unwrap_or_default(),
+        status = tracing::field::Empty,
+        latency_ms = tracing::field::Empty,
+    )
+}
+
+// Note how a request went in its span, to be logged when the span closes
+pub(crate) fn record_response<B>(response: &Response<B>, latency: Duration, span: &Span) {
+    span.record("status", response.status().as_u16());
+    span.record("latency_ms", latency.as_millis() as u64);
+}
+
+/// The ID of the request currently being handled. Outside of the
+/// `request_id` middleware a fresh ID is made up, so that error bodies always
+/// carry something a user can quote back to us.
+pub fn current_request_id() -> String {
+    REQUEST_ID
+        .try_with(|id| id.clone())
+        .unwrap_or_else(|_| uuid::Uuid::new_v4().to_string())
+}
@@ -0,0 +1,163 @@
+use serde::{Deserialize, Serialize};
+
+/// What a user is trusted to do, from most to least.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Can do anything, including deleting anyone's posts.
+    Admin,
+    /// Can edit anyone's posts and delete anyone's comments, but only delete
+    /// their own posts.
+    Editor,
+    /// Can write posts, and edit and delete their own.
+    #[default]
+    Author,
+    /// Can comment, but not write posts.
+    Reader,
+    /// Can't log in at all.
+    Denied,
+}
+
+/// Something a user might want to do, checked against their `Role`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Permission {
+    CreatePost,
+    EditPost,
+    DeletePost,
+    Comment,
+    DeleteComment,
+    /// Lift a lockout after too many failed logins.
+    UnlockLogins,
+}
+
+/// How far a role lets a user go with a `Permission`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Grant {
+    No,
+    /// Only for posts or comments the user wrote.
+    OwnOnly,
+    Yes,
+}
+
+impl Role {
+    /// The policy table:
+    ///
+    /// | Role   | Create post | Edit post | Delete post | Comment | Delete comment | Unlock logins |
+    /// |--------|-------------|-----------|-------------|---------|----------------|---------------|
+    /// | admin  | yes         | yes       | yes         | yes     | yes            | yes           |
+    /// | editor | yes         | yes       | own         | yes     | yes            | no            |
+    /// | author | yes         | own       | own         | yes     | own            | no            |
+    /// | reader | no          | no        | no          | yes     | own            | no            |
+    /// | denied | no          | no        | no          | no      | no             | no            |
+    pub fn grant(self, permission: Permission) -> Grant {
+        use Permission::*;
+
+        match (self, permission) {
+            (Role::Admin, _) => Grant::Yes,
+            (Role::Editor, DeletePost) => Grant::OwnOnly,
+            (Role::Editor, UnlockLogins) => Grant::No,
+            (Role::Editor, _) => Grant::Yes,
+            (Role::Author, CreatePost | Comment) => Grant::Yes,
+            (Role::Author, EditPost | DeletePost | DeleteComment) => Grant::OwnOnly,
+            (Role::Author, UnlockLogins) => Grant::No,
+            (Role::Reader, Comment) => Grant::Yes,
+            (Role::Reader, DeleteComment) => Grant::OwnOnly,
+            (Role::Reader, CreatePost | EditPost | DeletePost | UnlockLogins) => Grant::No,
+            (Role::Denied, _) => Grant::No,
+        }
+    }
+
+    /// May this role do `permission` to something that `is_owner` says
+    /// whether the user wrote?
+    pub fn allows(self, permission: Permission, is_owner: bool) -> bool {
+        match self.grant(permission) {
+            Grant::Yes => true,
+            Grant::OwnOnly => is_owner,
+            Grant::No => false,
+        }
+    }
+}
+
+impl Permission {
+    // What it's a permission to do, for telling users they can't
+    pub(crate) fn describe(self) -> &'static str {
+        match self {
+            Permission::CreatePost => "write posts",
+            Permission::EditPost => "edit this post",
+            Permission::DeletePost => "delete this post",
+            Permission::Comment => "comment",
+            Permission::DeleteComment => "delete this comment",
+            Permission::UnlockLogins => "unlock logins",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PERMISSIONS: [Permission; 6] = [
+        Permission::CreatePost,
+        Permission::EditPost,
+        Permission::DeletePost,
+        Permission::Comment,
+        Permission::DeleteComment,
+        Permission::UnlockLogins,
+    ];
+
+    // The policy table from `Role::grant`'s docs, in `PERMISSIONS` order
+    const POLICY: [(Role, [Grant; 6]); 5] = {
+        use Grant::*;
+        [
+            (Role::Admin, [Yes, Yes, Yes, Yes, Yes, Yes]),
+            (Role::Editor, [Yes, Yes, OwnOnly, Yes, Yes, No]),
+            (Role::Author, [Yes, OwnOnly, OwnOnly, Yes, OwnOnly, No]),
+            (Role::Reader, [No, No, No, Yes, OwnOnly, No]),
+            (Role::Denied, [No, No, No, No, No, No]),
+        ]
+    };
+
+    #[test]
+    fn grants_match_the_policy_table() {
+        for (role, grants) in POLICY {
+            for (permission, expected) in PERMISSIONS.into_iter().zip(grants) {
+                assert_eq!(role.grant(permission), expected, "{role:?} {permission:?}");
+                for is_owner in [false, true] {
+                    let allowed = match expected {
+                        Grant::Yes => true,
+                        Grant::OwnOnly => is_owner,
+                        Grant::No => false,
+                    };
+                    assert_eq!(
+                        role.allows(permission, is_owner),
+                        allowed,
+                        "{role:?} {permission:?} owner={is_owner}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn only_admins_delete_anyones_posts() {
+        for (role, _) in POLICY {
+            assert_eq!(role.allows(Permission::DeletePost, false), role == Role::Admin, "{role:?}");
+        }
+    }
+
+    #[test]
+    fn denied_users_can_never_write() {
+        for permission in PERMISSIONS {
+            for is_owner in [false, true] {
+                assert!(!Role::Denied.allows(permission, is_owner), "{permission:?} owner={is_owner}");
+            }
+        }
+    }
+
+    #[test]
+    fn roles_are_written_in_lowercase() {
+        assert_eq!(serde_json::to_string(&Role::Editor).unwrap(), r#""editor""#);
+        assert_eq!(serde_json::from_str::<Role>(r#""denied""#).unwrap(), Role::Denied);
+        assert_eq!(Role::default(), Role::Author);
+    }
+}
@@ -0,0 +1,360 @@
+use std::sync::Arc;
+
+use axum::extract::State;
+use axum::http::{header, StatusCode, Uri};
+use axum::response::{Html, Response};
+use axum::routing::{delete, get, post};
+use axum::{Json, Router};
+use blog_model::{BlogPost, Comment, CommentThread, NewComment, NewPost, PostPatch, SearchHit, TagCount};
+use serde::{Deserialize, Serialize};
+use tower_http::trace::TraceLayer;
+
+use crate::{
+    auth, feed, metrics, normalize_tags, pages, rate_limit, ApiError, ApiJson, ApiPath, ApiQuery, Auth, AuthUser, Conditions,
+    ListParams, Permission, PostStore, RateLimiter, RenderCache, RequestMetrics, SearchQuery, StoreVersion, Validators, DEFAULT_PAGE_SIZE,
+    MAX_PAGE_SIZE,
+};
+
+/// Everything the handlers need, handed to them by axum's `State` extractor.
+#[derive(Clone)]
+pub struct AppState {
+    pub store: Arc<dyn PostStore>,
+    pub rendered: Arc<RenderCache>,
+    pub requests: Arc<RequestMetrics>,
+    pub auth: Arc<Auth>,
+}
+
+/// Build the blog's routes on top of a post store, turning away clients that
+/// go over `limiter`'s budgets. Only users logged in through `auth` can make
+/// changes.
+pub fn router(store: Arc<dyn PostStore>, limiter: RateLimiter, auth: Auth) -> Router {
+    let state = AppState {
+        store,
+        rendered: Arc::new(RenderCache::default()),
+        requests: Arc::new(RequestMetrics::default()),
+        auth: Arc::new(auth),
+    };
+    Router::new()
+        .route("/", get(pages::index_page))
+        .route("/posts/:id", get(pages::post_page))
+        .route("/authors/:author", get(pages::author_page))
+        .route("/static/style.css", get(pages::stylesheet))
+        .route("/feed.rss", get(feed::rss_feed))
+        .route("/feed.atom", get(feed::atom_feed))
+        .route("/blog/all", get(all_posts))
+        .route("/blog/search", get(search_posts))
+        .route("/blog/tags", get(all_tags))
+        .route("/blog/tags/:tag", get(tagged_posts))
+        .route("/blog/:id/html", get(get_post_html))
+        .route("/blog/:id", get(get_post).put(replace_post).patch(update_post).delete(delete_post))
+        .route("/blog/new", post(new_post))
+        .route("/blog/:id/comments", get(post_comments).post(new_comment))
+        .route("/blog/:id/comments/:comment_id", delete(delete_comment))
+        .route("/auth/login", post(auth::login))
+        .route("/auth/lockouts/users/:username", delete(auth::unlock_user))
+        .route("/auth/lockouts/ips/:ip", delete(auth::unlock_ip))
+        .route("/metrics", get(metrics::metrics))
+        // Logged-in users are rate limited by name, so `authenticate` has to
+        // run before `rate_limit`
+        .layer(axum::middleware::from_fn_with_state(Arc::new(limiter), rate_limit::rate_limit))
+        .layer(axum::middleware::from_fn_with_state(state.auth.clone(), auth::authenticate))
+        .layer(axum::middleware::from_fn_with_state(state.clone(), metrics::track_requests))
+        // Give each request a span, logged with its status and how long it
+        // took. Layers wrap the ones added before them, so `request_id` runs
+        // first.
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(crate::request_id::request_span)
+                .on_response(crate::request_id::record_response),
+        )
+        .layer(axum::middleware::from_fn(crate::request_id))
+        .with_state(state)
+}
+
+// Return a page of blog posts, e.g. `/blog/all?sort=date&order=desc&limit=10`.
+// Pass the returned `next_cursor` back as `?cursor=` to get the next page.
+async fn all_posts(
+    State(state): State<AppState>,
+    conditions: Conditions,
+    uri: Uri,
+    ApiQuery(params): ApiQuery<ListParams>,
+) -> Result<Response, ApiError> {
+    let query = params.try_into()?;
+    let validators = Validators::for_store(state.store.version().await?, &uri);
+    if conditions.is_fresh(&validators) {
+        return Ok(validators.not_modified());
+    }
+    Ok(validators.attach(Json(state.store.list(&query).await?)))
+}
+
+// Return every tag in use, with how many posts carry it
+async fn all_tags(State(state): State<AppState>) -> Result<Json<Vec<TagCount>>, ApiError> {
+    Ok(Json(state.store.tags().await?))
+}
+
+// Return a page of the blog posts with a tag. Takes the same query string as
+// `/blog/all`.
+async fn tagged_posts(
+    State(state): State<AppState>,
+    conditions: Conditions,
+    uri: Uri,
+    ApiPath(tag): ApiPath<String>,
+    ApiQuery(params): ApiQuery<ListParams>,
+) -> Result<Response, ApiError> {
+    let query = ListParams { tag: Some(tag), ..params }.try_into()?;
+    let validators = Validators::for_store(state.store.version().await?, &uri);
+    if conditions.is_fresh(&validators) {
+        return Ok(validators.not_modified());
+    }
+    Ok(validators.attach(Json(state.store.list(&query).await?)))
+}
+
+#[derive(Deserialize)]
+struct PostParams {
+    #[serde(default)]
+    body_html: bool,
+}
+
+// A blog post, with its body rendered to HTML if that was asked for
+#[derive(Serialize)]
+struct PostWithHtml {
+    #[serde(flatten)]
+    post: BlogPost,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    body_html: Option<String>,
+}
+
+// Return a single blog post by ID number. `?body_html=true` adds the body
+// rendered from Markdown. The ETag is a hash of the JSON.
+async fn get_post(
+    State(state): State<AppState>,
+    conditions: Conditions,
+    ApiPath(id): ApiPath<i32>,
+    ApiQuery(params): ApiQuery<PostParams>,
+) -> Result<Response, ApiError> {
+    let version = state.store.version().await?;
+    let post = state.store.get(id).await?.ok_or(ApiError::NotFound)?;
+    let body_html = params.body_html.then(|| state.rendered.html(&post));
+    let (json, validators) = post_json(&PostWithHtml { post, body_html }, version);
+    if conditions.is_fresh(&validators) {
+        return Ok(validators.not_modified());
+    }
+    Ok(validators.attach(([(header::CONTENT_TYPE, "application/json")], json)))
+}
+
+// A post as JSON, with validators for it. The ETag of a post without
+// `body_html` is the one `If-Match` is checked against.
+fn post_json(post: &PostWithHtml, version: StoreVersion) -> (Vec<u8>, Validators) {
+    let json = serde_json::to_vec(post).expect("A post always serializes");
+    let validators = Validators::for_content(&json, version.modified);
+    (json, validators)
+}
+
+// Answer a change to a post with the post as it now is, and its new ETag
+async fn changed_post(state: &AppState, post: BlogPost) -> Result<Response, ApiError> {
+    state.rendered.invalidate(post.id);
+    let version = state.store.version().await?;
+    let (json, validators) = post_json(&PostWithHtml { post, body_html: None }, version);
+    Ok(validators.attach(([(header::CONTENT_TYPE, "application/json")], json)))
+}
+
+// Refuse with a 403 unless the user may do `permission` to a post
+async fn require_on_post(state: &AppState, user: &AuthUser, id: i32, permission: Permission) -> Result<(), ApiError> {
+    let post = state.store.get(id).await?.ok_or(ApiError::NotFound)?;
+    user.require(permission, Some(&post.author))
+}
+
+// Work out which version of a post the client means to change: the one whose
+// ETag they sent in If-Match, or else the one they named in the request
+async fn expected_version(
+    state: &AppState,
+    id: i32,
+    conditions: &Conditions,
+    version: Option<i64>,
+) -> Result<i64, ApiError> {
+    if !conditions.has_if_match() {
+        return version.ok_or(ApiError::PreconditionRequired);
+    }
+    let store_version = state.store.version().await?;
+    let post = state.store.get(id).await?.ok_or(ApiError::NotFound)?;
+    let expected = post.version;
+    let (_, validators) = post_json(&PostWithHtml { post, body_html: None }, store_version);
+    if conditions.if_match(&validators) {
+        Ok(expected)
+    } else {
+        Err(ApiError::PreconditionFailed)
+    }
+}
+
+// Return a blog post's body, rendered from Markdown to HTML
+async fn get_post_html(
+    State(state): State<AppState>,
+    conditions: Conditions,
+    ApiPath(id): ApiPath<i32>,
+) -> Result<Response, ApiError> {
+    let version = state.store.version().await?;
+    let post = state.store.get(id).await?.ok_or(ApiError::NotFound)?;
+    let html = state.rendered.html(&post);
+
+    let validators = Validators::for_content(html.as_bytes(), version.modified);
+    if conditions.is_fresh(&validators) {
+        return Ok(validators.not_modified());
+    }
+    Ok(validators.attach(Html(html)))
+}
+
+// Add a blog entry, returning the new ID number
+async fn new_post(
+    State(state): State<AppState>,
+    user: AuthUser,
+    ApiJson(mut post): ApiJson<NewPost>,
+) -> Result<Json<i32>, ApiError> {
+    user.require(Permission::CreatePost, None)?;
+    // Posts are written by whoever's logged in, whatever the body says
+    post.author = user.username;
+    post.tags = normalize_tags(post.tags);
+    let post = state.store.create(post).await?;
+    Ok(Json(post.id))
+}
+
+// The body of a PUT: the whole post, and the version it replaces if there's
+// no If-Match header
+#[derive(Deserialize)]
+struct Replacement {
+    #[serde(flatten)]
+    post: NewPost,
+    version: Option<i64>,
+}
+
+// The body of a PATCH: the fields to change, and the version they apply to if
+// there's no If-Match header
+#[derive(Deserialize)]
+struct VersionedPatch {
+    #[serde(flatten)]
+    patch: PostPatch,
+    version: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct DeleteParams {
+    version: Option<i64>,
+}
+
+// Replace a blog entry. The ID comes from the path, and the date and author
+// are kept. Changes need If-Match or a version, so nobody overwrites an edit
+// unseen.
+async fn replace_post(
+    State(state): State<AppState>,
+    user: AuthUser,
+    conditions: Conditions,
+    ApiPath(id): ApiPath<i32>,
+    ApiJson(body): ApiJson<Replacement>,
+) -> Result<Response, ApiError> {
+    require_on_post(&state, &user, id, Permission::EditPost).await?;
+    let expected = expected_version(&state, id, &conditions, body.version).await?;
+    let mut post = body.post;
+    post.tags = normalize_tags(post.tags);
+    let patch = PostPatch {
+        author: None,
+        ..post.into()
+    };
+    let post = state.store.update(id, patch, expected).await?.ok_or(ApiError::NotFound)?;
+    changed_post(&state, post).await
+}
+
+// Update some of the fields of a blog entry. A post's author and date can't be
+// changed.
+async fn update_post(
+    State(state): State<AppState>,
+    user: AuthUser,
+    conditions: Conditions,
+    ApiPath(id): ApiPath<i32>,
+    ApiJson(body): ApiJson<VersionedPatch>,
+) -> Result<Response, ApiError> {
+    require_on_post(&state, &user, id, Permission::EditPost).await?;
+    let expected = expected_version(&state, id, &conditions, body.version).await?;
+    let mut patch = body.patch;
+    patch.author = None;
+    patch.date = None;
+    patch.tags = patch.tags.map(normalize_tags);
+    let post = state.store.update(id, patch, expected).await?.ok_or(ApiError::NotFound)?;
+    changed_post(&state, post).await
+}
+
+// Remove a blog entry. Takes If-Match, or the version as `?version=`.
+async fn delete_post(
+    State(state): State<AppState>,
+    user: AuthUser,
+    conditions: Conditions,
+    ApiPath(id): ApiPath<i32>,
+    ApiQuery(params): ApiQuery<DeleteParams>,
+) -> Result<StatusCode, ApiError> {
+    require_on_post(&state, &user, id, Permission::DeletePost).await?;
+    let expected = expected_version(&state, id, &conditions, params.version).await?;
+    if state.store.delete(id, expected).await? {
+        state.rendered.invalidate(id);
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::NotFound)
+    }
+}
+
+#[derive(Deserialize)]
+struct SearchParams {
+    q: String,
+    limit: Option<usize>,
+}
+
+// Find blog entries by the words in them, e.g. `/blog/search?q="call me" whal*`
+async fn search_posts(
+    State(state): State<AppState>,
+    ApiQuery(params): ApiQuery<SearchParams>,
+) -> Result<Json<Vec<SearchHit>>, ApiError> {
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(ApiError::BadRequest(format!("limit must be between 1 and {MAX_PAGE_SIZE}")));
+    }
+    let query = SearchQuery::parse(&params.q)?;
+    Ok(Json(state.store.search(&query, limit).await?))
+}
+
+// Return a post's comments, arranged into threads of replies
+async fn post_comments(
+    State(state): State<AppState>,
+    ApiPath(id): ApiPath<i32>,
+) -> Result<Json<Vec<CommentThread>>, ApiError> {
+    let comments = state.store.comments(id).await?.ok_or(ApiError::NotFound)?;
+    Ok(Json(CommentThread::build(comments)))
+}
+
+// Comment on a post, or reply to a comment
+async fn new_comment(
+    State(state): State<AppState>,
+    user: AuthUser,
+    ApiPath(id): ApiPath<i32>,
+    ApiJson(mut comment): ApiJson<NewComment>,
+) -> Result<(StatusCode, Json<Comment>), ApiError> {
+    user.require(Permission::Comment, None)?;
+    if comment.body.trim().is_empty() {
+        return Err(ApiError::BadRequest("A comment needs a body".to_string()));
+    }
+    comment.author = user.username;
+    let comment = state.store.add_comment(id, comment).await?.ok_or(ApiError::NotFound)?;
+    Ok((StatusCode::CREATED, Json(comment)))
+}
+
+// Remove a comment
+async fn delete_comment(
+    State(state): State<AppState>,
+    user: AuthUser,
+    ApiPath((id, comment_id)): ApiPath<(i32, i32)>,
+) -> Result<StatusCode, ApiError> {
+    let comments = state.store.comments(id).await?.ok_or(ApiError::NotFound)?;
+    let comment = comments.iter().find(|comment| comment.id == comment_id).ok_or(ApiError::NotFound)?;
+    user.require(Permission::DeleteComment, Some(&comment.author))?;
+    if state.store.delete_comment(id, comment_id).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::NotFound)
+    }
+}
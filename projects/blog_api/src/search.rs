@@ -0,0 +1,89 @@
+use crate::ApiError;
+
+/// Marks the start of a highlighted match in a raw snippet.
+pub const MARK_START: char = '\u{2}';
+
+/// Marks the end of a highlighted match in a raw snippet.
+pub const MARK_END: char = '\u{3}';
+
+/// One part of a search query. Words are lower-cased and split on anything
+/// that isn't a letter or a digit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SearchTerm {
+    /// A single word, e.g. `whale`.
+    Word(String),
+    /// Any word starting with this, e.g. `whal*`.
+    Prefix(String),
+    /// Words that must appear next to each other, e.g. `"call me ishmael"`.
+    Phrase(Vec<String>),
+}
+
+/// A parsed search query. A post matches when it matches every term.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SearchQuery {
+    pub terms: Vec<SearchTerm>,
+}
+
+impl SearchQuery {
+    /// Parse the text a user typed into a search box. Double quotes group a
+    /// phrase, and a trailing `*` makes a word a prefix.
+    pub fn parse(query: &str) -> Result<Self, ApiError> {
+        let mut terms = Vec::new();
+        let mut rest = query;
+        while let Some(start) = rest.find(|c: char| !c.is_whitespace()) {
+            rest = &rest[start..];
+            let (token, quoted) = if let Some(phrase) = rest.strip_prefix('"') {
+                let end = phrase.find('"').unwrap_or(phrase.len());
+                rest = phrase.get(end + 1..).unwrap_or("");
+                (&phrase[..end], true)
+            } else {
+                let end = rest.find(|c: char| c.is_whitespace() || c == '"').unwrap_or(rest.len());
+                let token = &rest[..end];
+                rest = &rest[end..];
+                (token, false)
+            };
+
+            let mut words = words(token);
+            let term = match words.len() {
+                0 => continue,
+                1 if !quoted && token.ends_with('*') => SearchTerm::Prefix(words.remove(0)),
+                1 => SearchTerm::Word(words.remove(0)),
+                _ => SearchTerm::Phrase(words),
+            };
+            terms.push(term);
+        }
+
+        if terms.is_empty() {
+            return Err(ApiError::BadRequest("The search query has no words in it".to_string()));
+        }
+        Ok(Self { terms })
+    }
+}
+
+/// Split text into lower-case words.
+pub fn words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect()
+}
+
+/// Turn a raw snippet, with matches wrapped in `MARK_START` and `MARK_END`,
+/// into HTML that is safe to display: everything is escaped, and the matches
+/// are wrapped in `<mark>`.
+pub fn snippet_to_html(raw: &str) -> String {
+    let mut html = String::with_capacity(raw.len());
+    for c in raw.chars() {
+        match c {
+            MARK_START => html.push_str("<mark>"),
+            MARK_END => html.push_str("</mark>"),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+    html
+}
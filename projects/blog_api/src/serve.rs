@@ -0,0 +1,85 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use axum::Router;
+use tokio::sync::watch;
+use tokio::task::{JoinError, JoinSet};
+
+/// Serve `app` on every address in `listen` until the process is asked to
+/// stop, with Ctrl+C or SIGTERM. Then stop accepting connections and wait up
+/// to `drain_timeout` for requests in progress to finish.
+///
+/// If one of the servers fails, the rest are stopped the same way and the
+/// error is returned, so the caller can always close its store afterwards.
+pub async fn serve(app: Router, listen: &[SocketAddr], drain_timeout: Duration) -> Result<(), String> {
+    let (stop, stopping) = watch::channel(false);
+    let mut servers = JoinSet::new();
+    for addr in listen {
+        let server = axum::Server::try_bind(addr).map_err(|e| format!("Unable to listen on {addr}: {e}"))?;
+        let mut stopping = stopping.clone();
+        let server = server
+            .serve(app.clone().into_make_service_with_connect_info::<SocketAddr>())
+            .with_graceful_shutdown(async move {
+                // An error means `stop` is gone, which only happens once we're
+                // stopping anyway
+                let _ = stopping.changed().await;
+            });
+        servers.spawn(server);
+        tracing::info!("Listening on http://{addr}");
+    }
+
+    let mut failure = None;
+    tokio::select! {
+        _ = shutdown_signal() => tracing::info!("Shutting down, once the requests in progress have finished"),
+        Some(result) = servers.join_next() => failure = failed(result),
+    }
+
+    stop.send_replace(true);
+    let drained = tokio::time::timeout(drain_timeout, async {
+        while let Some(result) = servers.join_next().await {
+            failure = failure.take().or_else(|| failed(result));
+        }
+    })
+    .await;
+    if drained.is_err() {
+        tracing::warn!("Gave up waiting for requests in progress after {drain_timeout:?}");
+        servers.shutdown().await;
+    }
+
+    match failure {
+        Some(message) => Err(message),
+        None => Ok(()),
+    }
+}
+
+// Why a server task stopped, if it wasn't asked to
+fn failed(result: Result<Result<(), impl std::fmt::Display>, JoinError>) -> Option<String> {
+    match result {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(format!("The server failed: {e}")),
+        Err(e) => Some(format!("The server panicked: {e}")),
+    }
+}
+
+// Wait for Ctrl+C, or on Unix for SIGTERM, which is how service managers and
+// container runtimes ask a process to stop
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c().await.expect("Unable to listen for Ctrl+C");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Unable to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
@@ -0,0 +1,92 @@
+use async_trait::async_trait;
+
+use blog_model::{BlogPost, Comment, NewComment, NewPost, PostPage, PostPatch, SearchHit, TagCount};
+use chrono::{DateTime, Utc};
+
+use crate::{ApiError, ListQuery, MetricsText, SearchQuery};
+
+/// Somewhere to keep blog posts. The router only ever talks to this trait,
+/// so the in-memory and SQLite servers share every handler.
+///
+/// Tags arrive already cleaned up by `normalize_tags`.
+///
+/// Implementations report a missing post with `Ok(None)` (or `Ok(false)` for
+/// `delete`); `Err` is reserved for the store itself failing.
+#[async_trait]
+pub trait PostStore: Send + Sync {
+    /// One page of posts, filtered and sorted as the query asks. `next_cursor`
+    /// is set whenever there may be more posts to fetch.
+    async fn list(&self, query: &ListQuery) -> Result<PostPage, ApiError>;
+
+    /// A single post by ID.
+    async fn get(&self, id: i32) -> Result<Option<BlogPost>, ApiError>;
+
+    /// Store a new post, dated now and at `BlogPost::FIRST_VERSION`, returning
+    /// it with its newly allocated ID.
+    async fn create(&self, post: NewPost) -> Result<BlogPost, ApiError>;
+
+    /// Change the supplied fields of a post and bump its version, returning
+    /// the updated post. Fails with `PreconditionFailed`, changing nothing,
+    /// unless the post is still at `expected_version`; the check and the
+    /// update must happen atomically.
+    async fn update(&self, id: i32, patch: PostPatch, expected_version: i64) -> Result<Option<BlogPost>, ApiError>;
+
+    /// Remove a post. Returns `false` if there was no such post, and fails
+    /// with `PreconditionFailed` unless it's still at `expected_version`.
+    async fn delete(&self, id: i32, expected_version: i64) -> Result<bool, ApiError>;
+
+    /// Up to `limit` posts matching every term of the query, best match
+    /// first. Snippets are HTML, made with `snippet_to_html`.
+    async fn search(&self, query: &SearchQuery, limit: usize) -> Result<Vec<SearchHit>, ApiError>;
+
+    /// Every tag in use, with how many posts carry it, in name order.
+    async fn tags(&self) -> Result<Vec<TagCount>, ApiError>;
+
+    /// All of a post's comments, oldest first, or `None` if there's no such
+    /// post.
+    async fn comments(&self, post_id: i32) -> Result<Option<Vec<Comment>>, ApiError>;
+
+    /// Add a comment to a post, dated now. Returns `None` if there's no such
+    /// post, and a `BadRequest` if the parent isn't a live comment on the
+    /// same post.
+    async fn add_comment(&self, post_id: i32, comment: NewComment) -> Result<Option<Comment>, ApiError>;
+
+    /// Delete a comment. One with replies becomes a tombstone; one without is
+    /// removed, along with any tombstones left with nothing under them.
+    /// Returns `false` if the post has no such comment.
+    async fn delete_comment(&self, post_id: i32, comment_id: i32) -> Result<bool, ApiError>;
+
+    /// How many times the store has changed, and when it last did. Any
+    /// change to a post, its tags or its comments counts.
+    async fn version(&self) -> Result<StoreVersion, ApiError>;
+
+    /// Add the store's own metrics to `/metrics`: at least how many posts
+    /// and comments it holds, as `blog_posts` and `blog_comments` gauges.
+    async fn write_metrics(&self, out: &mut MetricsText) -> Result<(), ApiError>;
+
+    /// Save anything not yet saved and let go of the store's resources. This
+    /// is called once, when the server stops, after the last request.
+    async fn close(&self) -> Result<(), ApiError>;
+}
+
+/// A store-wide version stamp, so responses built from many posts can be
+/// cached without looking at the posts. Together, `version` and `modified`
+/// change whenever anything in the store does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StoreVersion {
+    pub version: i64,
+    pub modified: DateTime<Utc>,
+}
+
+/// Tidy up tags supplied by a client: trimmed, lower case, sorted, with
+/// blanks and duplicates removed.
+pub fn normalize_tags(tags: Vec<String>) -> Vec<String> {
+    let mut tags: Vec<String> = tags
+        .into_iter()
+        .map(|tag| tag.trim().to_lowercase())
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    tags.sort();
+    tags.dedup();
+    tags
+}
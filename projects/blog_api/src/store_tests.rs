@@ -0,0 +1,325 @@
+//! Checks that any `PostStore` should pass, so that every store is held to
+//! the same behaviour. Each store's own tests run them against a new, empty
+//! store, e.g.
+//!
+//! ```ignore
+//! #[tokio::test]
+//! async fn lists() {
+//!     blog_api::store_tests::lists(&MemoryStore::new()).await;
+//! }
+//! ```
+//!
+//! Stores that start with posts of their own should delete them first. IDs
+//! are never assumed, only taken from what the store hands back.
+
+use blog_model::{BlogPost, NewComment, NewPost, PostPatch};
+use chrono::{DateTime, NaiveDate, Utc};
+
+use crate::{ApiError, Cursor, ListParams, ListQuery, PostStore, SearchQuery, SortField, SortOrder};
+
+fn new_post(title: &str, body: &str, author: &str, tags: &[&str]) -> NewPost {
+    NewPost {
+        title: title.to_string(),
+        body: body.to_string(),
+        author: author.to_string(),
+        tags: tags.iter().map(|tag| tag.to_string()).collect(),
+    }
+}
+
+fn day(day: u32) -> DateTime<Utc> {
+    NaiveDate::from_ymd_opt(2023, 9, day).unwrap().and_hms_opt(12, 0, 0).unwrap().and_utc()
+}
+
+// Create a post, then move it to `date`, as stores date new posts now
+async fn create_on(store: &dyn PostStore, date: DateTime<Utc>, post: NewPost) -> BlogPost {
+    let created = store.create(post).await.unwrap();
+    let patch = PostPatch {
+        date: Some(date),
+        ..PostPatch::default()
+    };
+    store.update(created.id, patch, created.version).await.unwrap().unwrap()
+}
+
+fn query(params: ListParams) -> ListQuery {
+    ListQuery::try_from(params).unwrap()
+}
+
+async fn titles(store: &dyn PostStore, query: &ListQuery) -> Vec<String> {
+    let page = store.list(query).await.unwrap();
+    page.posts.into_iter().map(|post| post.title).collect()
+}
+
+// Every post `query` lists, fetched `limit` at a time
+async fn all_pages(store: &dyn PostStore, mut query: ListQuery, limit: usize) -> Vec<String> {
+    query.limit = limit;
+    let mut titles = vec![];
+    loop {
+        let page = store.list(&query).await.unwrap();
+        titles.extend(page.posts.into_iter().map(|post| post.title));
+        let Some(cursor) = page.next_cursor else {
+            return titles;
+        };
+        let params = ListParams {
+            cursor: Some(cursor),
+            sort: Some(query.sort),
+            order: Some(query.order),
+            ..ListParams::default()
+        };
+        query.after = ListQuery::try_from(params).unwrap().after;
+    }
+}
+
+/// Filtering by author, tag and date, sorting every way with ties broken by
+/// ID, and paging through the lot with cursors.
+pub async fn lists(store: &dyn PostStore) {
+    create_on(store, day(3), new_post("Whales", "", "herbert", &["sea"])).await;
+    create_on(store, day(1), new_post("Ships", "", "Herbert", &["boats", "sea"])).await;
+    create_on(store, day(2), new_post("Gophers", "", "ashley", &["go"])).await;
+    create_on(store, day(2), new_post("Boats", "", "ashley", &["boats"])).await;
+    create_on(store, day(4), new_post("Ships", "", "ashley", &[])).await;
+
+    let by_id = query(ListParams::default());
+    assert_eq!(titles(store, &by_id).await, ["Whales", "Ships", "Gophers", "Boats", "Ships"]);
+    let by_date = query(ListParams {
+        sort: Some(SortField::Date),
+        ..ListParams::default()
+    });
+    assert_eq!(titles(store, &by_date).await, ["Ships", "Gophers", "Boats", "Whales", "Ships"]);
+    let by_title_desc = query(ListParams {
+        sort: Some(SortField::Title),
+        order: Some(SortOrder::Desc),
+        ..ListParams::default()
+    });
+    assert_eq!(titles(store, &by_title_desc).await, ["Whales", "Ships", "Ships", "Gophers", "Boats"]);
+
+    let by_author = query(ListParams {
+        author: Some("HERBERT".to_string()),
+        ..ListParams::default()
+    });
+    assert_eq!(titles(store, &by_author).await, ["Whales", "Ships"]);
+    let by_tag = query(ListParams {
+        tag: Some("boats".to_string()),
+        ..ListParams::default()
+    });
+    assert_eq!(titles(store, &by_tag).await, ["Ships", "Boats"]);
+    let by_dates = query(ListParams {
+        since: Some("2023-09-02".to_string()),
+        until: Some("2023-09-03T12:00:00Z".to_string()),
+        ..ListParams::default()
+    });
+    assert_eq!(titles(store, &by_dates).await, ["Gophers", "Boats"]);
+
+    for sort in [SortField::Id, SortField::Date, SortField::Title] {
+        for order in [SortOrder::Asc, SortOrder::Desc] {
+            let query = query(ListParams {
+                sort: Some(sort),
+                order: Some(order),
+                ..ListParams::default()
+            });
+            let expected = titles(store, &query).await;
+            for limit in 1..=5 {
+                assert_eq!(all_pages(store, query.clone(), limit).await, expected, "{sort:?} {order:?} {limit}");
+            }
+        }
+    }
+    let page = store.list(&ListQuery { limit: 5, ..by_id }).await.unwrap();
+    assert_eq!(page.next_cursor, None);
+}
+
+/// A cursor without the key it's sorted by sorts after every post, so
+/// nothing comes after it going up, and everything does going down.
+pub async fn keyless_cursors_sort_last(store: &dyn PostStore) {
+    let whales = create_on(store, day(3), new_post("Whales", "", "herbert", &[])).await;
+    create_on(store, day(1), new_post("Ships", "", "herbert", &[])).await;
+
+    for sort in [SortField::Date, SortField::Title] {
+        for (order, expected) in [(SortOrder::Asc, vec![]), (SortOrder::Desc, vec!["Whales", "Ships"])] {
+            let mut query = query(ListParams {
+                sort: Some(sort),
+                order: Some(order),
+                ..ListParams::default()
+            });
+            query.after = Some(Cursor {
+                sort,
+                order,
+                date: None,
+                title: None,
+                id: whales.id,
+            });
+            assert_eq!(titles(store, &query).await, expected, "{sort:?} {order:?}");
+        }
+    }
+}
+
+async fn search_ids(store: &dyn PostStore, query: &str) -> Vec<i32> {
+    let hits = store.search(&SearchQuery::parse(query).unwrap(), 10).await.unwrap();
+    let mut ids: Vec<i32> = hits.into_iter().map(|hit| hit.post.id).collect();
+    ids.sort();
+    ids
+}
+
+/// Searching matches any form of a word, every term, phrases in order and
+/// prefixes, ranks titles first, and keeps up as posts change.
+pub async fn searches(store: &dyn PostStore) {
+    let mut posts = vec![];
+    for (title, body) in [
+        ("Moby Dick", "Call me Ishmael. Some years ago I went whaling."),
+        ("Whales", "The whale is the largest animal in the sea."),
+        ("Boats", "Ishmael, call me later about the boats."),
+        ("Hopping", "Rabbits were hopping and running quickly."),
+    ] {
+        posts.push(store.create(new_post(title, body, "herbert", &[])).await.unwrap());
+    }
+    let [moby, whales, boats, hopping] = <[BlogPost; 4]>::try_from(posts).unwrap();
+
+    assert_eq!(search_ids(store, "whale").await, [moby.id, whales.id]);
+    assert_eq!(search_ids(store, "Whaling").await, [moby.id, whales.id]);
+    assert_eq!(search_ids(store, "squid").await, [] as [i32; 0]);
+    assert_eq!(search_ids(store, "ishmael call").await, [moby.id, boats.id]);
+    assert_eq!(search_ids(store, "ishmael whale").await, [moby.id]);
+    assert_eq!(search_ids(store, r#""call me ishmael""#).await, [moby.id]);
+    assert_eq!(search_ids(store, r#""ishmael call me""#).await, [boats.id]);
+    assert_eq!(search_ids(store, r#""me call""#).await, [] as [i32; 0]);
+    assert_eq!(search_ids(store, "whal*").await, [moby.id, whales.id]);
+    assert_eq!(search_ids(store, "rabbit*").await, [hopping.id]);
+    assert_eq!(search_ids(store, "ish*").await, [moby.id, boats.id]);
+
+    let hits = store.search(&SearchQuery::parse("whale").unwrap(), 10).await.unwrap();
+    assert_eq!(hits[0].post.id, whales.id);
+    assert!(hits[0].score > hits[1].score);
+    assert!(hits.iter().all(|hit| hit.score > 0.0));
+    // The snippet may come from the title or the body
+    assert!(hits[0].snippet.to_lowercase().contains("<mark>whale"), "{}", hits[0].snippet);
+    let limited = store.search(&SearchQuery::parse("whale").unwrap(), 1).await.unwrap();
+    assert_eq!(limited.len(), 1);
+
+    let patch = PostPatch {
+        title: Some("Squid".to_string()),
+        body: Some("Tentacles".to_string()),
+        ..PostPatch::default()
+    };
+    store.update(whales.id, patch, whales.version).await.unwrap().unwrap();
+    assert_eq!(search_ids(store, "whale").await, [moby.id]);
+    assert_eq!(search_ids(store, "squid").await, [whales.id]);
+    assert!(store.delete(moby.id, moby.version).await.unwrap());
+    assert_eq!(search_ids(store, "whale").await, [] as [i32; 0]);
+    assert_eq!(search_ids(store, "ishmael").await, [boats.id]);
+}
+
+async fn tag_counts(store: &dyn PostStore) -> Vec<(String, i64)> {
+    let tags = store.tags().await.unwrap();
+    tags.into_iter().map(|count| (count.tag, count.posts)).collect()
+}
+
+/// Tags are counted in name order, and forgotten once no post carries them.
+pub async fn tags(store: &dyn PostStore) {
+    let whales = store.create(new_post("Whales", "", "herbert", &["animals", "sea"])).await.unwrap();
+    let ships = store.create(new_post("Ships", "", "herbert", &["boats", "sea"])).await.unwrap();
+    assert_eq!(whales.tags, ["animals", "sea"]);
+    let counts = |counts: &[(&str, i64)]| counts.iter().map(|(tag, n)| (tag.to_string(), *n)).collect::<Vec<_>>();
+    assert_eq!(tag_counts(store).await, counts(&[("animals", 1), ("boats", 1), ("sea", 2)]));
+
+    let patch = PostPatch {
+        tags: Some(vec!["sea".to_string()]),
+        ..PostPatch::default()
+    };
+    let whales = store.update(whales.id, patch, whales.version).await.unwrap().unwrap();
+    assert_eq!(whales.tags, ["sea"]);
+    assert_eq!(tag_counts(store).await, counts(&[("boats", 1), ("sea", 2)]));
+
+    // A patch without tags leaves them alone
+    let patch = PostPatch {
+        title: Some("Big whales".to_string()),
+        ..PostPatch::default()
+    };
+    let whales = store.update(whales.id, patch, whales.version).await.unwrap().unwrap();
+    assert_eq!(whales.tags, ["sea"]);
+
+    assert!(store.delete(ships.id, ships.version).await.unwrap());
+    assert_eq!(tag_counts(store).await, counts(&[("sea", 1)]));
+    assert!(store.delete(whales.id, whales.version).await.unwrap());
+    assert_eq!(tag_counts(store).await, []);
+}
+
+fn comment(body: &str, parent_id: Option<i32>) -> NewComment {
+    NewComment {
+        author: "ashley".to_string(),
+        body: body.to_string(),
+        parent_id,
+    }
+}
+
+/// Deleted comments with replies stay as tombstones, which go once their
+/// last reply does, and a post's comments go with it.
+pub async fn comments(store: &dyn PostStore) {
+    let whales = store.create(new_post("Whales", "", "herbert", &[])).await.unwrap();
+    let ships = store.create(new_post("Ships", "", "herbert", &[])).await.unwrap();
+    assert_eq!(store.comments(whales.id).await.unwrap(), Some(vec![]));
+    assert_eq!(store.comments(-1).await.unwrap(), None);
+    assert_eq!(store.add_comment(-1, comment("Hello?", None)).await.unwrap(), None);
+
+    let first = store.add_comment(whales.id, comment("Big", None)).await.unwrap().unwrap();
+    let reply = store.add_comment(whales.id, comment("Very", Some(first.id))).await.unwrap().unwrap();
+    let nested = store.add_comment(whales.id, comment("Really", Some(reply.id))).await.unwrap().unwrap();
+    let other = store.add_comment(ships.id, comment("Wet", None)).await.unwrap().unwrap();
+    assert_eq!((first.post_id, first.author.as_str(), first.deleted), (whales.id, "ashley", false));
+    let elsewhere = store.add_comment(ships.id, comment("Lost", Some(first.id))).await;
+    assert!(matches!(elsewhere, Err(ApiError::BadRequest(_))), "{elsewhere:?}");
+
+    // Comments are only deleted from their own post
+    assert!(!store.delete_comment(ships.id, first.id).await.unwrap());
+    assert!(store.delete_comment(whales.id, first.id).await.unwrap());
+    let comments = store.comments(whales.id).await.unwrap().unwrap();
+    assert_eq!(comments.iter().map(|c| c.id).collect::<Vec<_>>(), [first.id, reply.id, nested.id]);
+    assert!(comments[0].deleted);
+    assert_eq!((comments[0].author.as_str(), comments[0].body.as_str()), ("", ""));
+    let to_tombstone = store.add_comment(whales.id, comment("Hello?", Some(first.id))).await;
+    assert!(matches!(to_tombstone, Err(ApiError::BadRequest(_))), "{to_tombstone:?}");
+
+    assert!(store.delete_comment(whales.id, reply.id).await.unwrap());
+    let comments = store.comments(whales.id).await.unwrap().unwrap();
+    let states: Vec<_> = comments.iter().map(|c| (c.id, c.deleted)).collect();
+    assert_eq!(states, [(first.id, true), (reply.id, true), (nested.id, false)]);
+
+    // Deleting the last reply takes the tombstones above it with it
+    assert!(store.delete_comment(whales.id, nested.id).await.unwrap());
+    assert_eq!(store.comments(whales.id).await.unwrap(), Some(vec![]));
+    assert!(!store.delete_comment(whales.id, nested.id).await.unwrap());
+
+    assert!(store.delete(ships.id, ships.version).await.unwrap());
+    assert_eq!(store.comments(ships.id).await.unwrap(), None);
+    assert!(!store.delete_comment(ships.id, other.id).await.unwrap());
+}
+
+/// Changes only happen to the version of a post they were made against, and
+/// every change moves the store's version on.
+pub async fn versions(store: &dyn PostStore) {
+    let start = store.version().await.unwrap();
+    let whales = store.create(new_post("Whales", "", "herbert", &[])).await.unwrap();
+    assert_eq!(whales.version, BlogPost::FIRST_VERSION);
+    let created = store.version().await.unwrap();
+    assert!(created.version > start.version && created.modified >= start.modified);
+
+    let patch = |title: &str| PostPatch {
+        title: Some(title.to_string()),
+        ..PostPatch::default()
+    };
+    let stale = store.update(whales.id, patch("Orcas"), whales.version + 1).await;
+    assert!(matches!(stale, Err(ApiError::PreconditionFailed)), "{stale:?}");
+    assert_eq!(store.get(whales.id).await.unwrap().as_ref(), Some(&whales));
+    assert_eq!(store.version().await.unwrap().version, created.version);
+
+    let updated = store.update(whales.id, patch("Big whales"), whales.version).await.unwrap().unwrap();
+    assert_eq!((updated.title.as_str(), updated.version), ("Big whales", whales.version + 1));
+    let again = store.update(whales.id, patch("Orcas"), whales.version).await;
+    assert!(matches!(again, Err(ApiError::PreconditionFailed)), "{again:?}");
+    assert!(store.version().await.unwrap().version > created.version);
+
+    assert_eq!(store.update(-1, patch("Orcas"), 1).await.unwrap(), None);
+    assert!(!store.delete(-1, 1).await.unwrap());
+    let stale = store.delete(whales.id, whales.version).await;
+    assert!(matches!(stale, Err(ApiError::PreconditionFailed)), "{stale:?}");
+    assert!(store.get(whales.id).await.unwrap().is_some());
+    assert!(store.delete(whales.id, updated.version).await.unwrap());
+    assert_eq!(store.get(whales.id).await.unwrap(), None);
+}
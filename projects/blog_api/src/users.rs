@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use chrono::{DateTime, Utc};
+use passwords::{Params, PasswordHasher, Verified};
+use serde::{Deserialize, Serialize};
+
+use crate::Role;
+
+/// Someone who can log in to write posts.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(from = "UserRecord")]
+pub struct User {
+    pub username: String,
+    /// An Argon2id hash of the user's password, in the PHC string format
+    /// (`$argon2id$v=19$m=...`). Anything that isn't a PHC string is taken
+    /// to be a plain password, so old users files keep working, and is
+    /// replaced by a hash the first time the user logs in.
+    pub password: String,
+    pub role: Role,
+}
+
+// A user as written in a users file. Files from before there were roles only
+// mark admins, so everyone else there is an author.
+#[derive(Deserialize)]
+struct UserRecord {
+    username: String,
+    password: String,
+    role: Option<Role>,
+    #[serde(default)]
+    is_admin: bool,
+}
+
+impl From<UserRecord> for User {
+    fn from(record: UserRecord) -> Self {
+        let role = match (record.role, record.is_admin) {
+            (Some(role), _) => role,
+            (None, true) => Role::Admin,
+            (None, false) => Role::Author,
+        };
+        User {
+            username: record.username,
+            password: record.password,
+            role,
+        }
+    }
+}
+
+/// How an attempt to log in went.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LoginResult {
+    Success { role: Role },
+    /// The password was right, but the user isn't allowed to log in.
+    Denied,
+    Failure,
+    /// There have been too many failed logins for the user, or from where
+    /// they're logging in, so they can't try again until `until`.
+    LockedOut { until: DateTime<Utc> },
+}
+
+/// Everyone who can log in, by username.
+pub struct Users {
+    hasher: PasswordHasher,
+    by_name: RwLock<HashMap<String, User>>,
+    // Where the users came from, so upgraded passwords can be saved
+    path: Option<PathBuf>,
+    // Checked against when there's no such user, so that takes as long as a
+    // wrong password and doesn't give away who has an account
+    dummy_hash: String,
+}
+
+impl Default for Users {
+    fn default() -> Self {
+        Self::new(vec![])
+    }
+}
+
+impl Users {
+    /// Users whose passwords are hashed with Argon2id's default parameters.
+    pub fn new(users: Vec<User>) -> Self {
+        Self::with_params(users, Params::default())
+    }
+
+    /// Users whose passwords are hashed with Argon2id and `params`. Hashes
+    /// made with other parameters still work, and are redone with these the
+    /// next time their user logs in.
+    pub fn with_params(users: Vec<User>, params: Params) -> Self {
+        let hasher = PasswordHasher::new(params);
+        let dummy_hash = hasher.hash("");
+        Self {
+            hasher,
+            by_name: RwLock::new(users.into_iter().map(|user| (user.username.clone(), user)).collect()),
+            path: None,
+            dummy_hash,
+        }
+    }
+
+    /// Read users from a JSON file holding a list of them, e.g.
+    /// `[{ "username": "admin", "password": "$argon2id$...", "role": "admin" }]`.
+    /// Passwords that are upgraded when their users log in are written back
+    /// to the file.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {e}", path.display()))?;
+        let users = serde_json::from_str(&text).map_err(|e| format!("Invalid users in {}: {e}", path.display()))?;
+        Ok(Self {
+            path: Some(path.to_path_buf()),
+            ..Self::new(users)
+        })
+    }
+
+    /// A hash of `password` to store in a `User`.
+    pub fn hash_password(&self, password: &str) -> String {
+        self.hasher.hash(password)
+    }
+
+    /// Check a username and password. Hashing is deliberately slow, so call
+    /// this from a blocking task rather than straight from async code.
+    pub fn is_login_valid(&self, username: &str, password: &str) -> LoginResult {
+        let user = self.by_name.read().unwrap().get(username).cloned();
+        let Some(user) = user else {
+            let _ = self.verify(&self.dummy_hash, password);
+            return LoginResult::Failure;
+        };
+
+        match self.verify(&user.password, password) {
+            Verified::No => return LoginResult::Failure,
+            Verified::Yes => {}
+            Verified::NeedsRehash => self.set_password(username, password),
+        }
+        match user.role {
+            Role::Denied => LoginResult::Denied,
+            role => LoginResult::Success { role },
+        }
+    }
+
+    /// A user's role, if there's such a user.
+    pub fn role(&self, username: &str) -> Option<Role> {
+        self.by_name.read().unwrap().get(username).map(|user| user.role)
+    }
+
+    fn verify(&self, stored: &str, password: &str) -> Verified {
+        self.hasher.verify(stored, password).unwrap_or_else(|e| {
+            tracing::warn!("A user can't log in, because {e}");
+            Verified::No
+        })
+    }
+
+    // Store a fresh hash of a user's password, and save it if the users came
+    // from a file. A failure to save isn't the user's problem - they're
+    // logged in either way, and it'll be tried again next time.
+    fn set_password(&self, username: &str, password: &str) {
+        let password = self.hash_password(password);
+        let mut by_name = self.by_name.write().unwrap();
+        if let Some(user) = by_name.get_mut(username) {
+            user.password = password;
+        }
+        if let Some(path) = &self.path {
+            match save(path, &by_name) {
+                Ok(()) => tracing::info!(username, "Upgraded a user's password hash"),
+                Err(e) => tracing::error!("Unable to save {}: {e}", path.display()),
+            }
+        }
+    }
+}
+
+// Write the users to a temporary file and rename it into place, so a crash
+// leaves either the old file or the new one
+fn save(path: &Path, by_name: &HashMap<String, User>) -> std::io::Result<()> {
+    let mut users: Vec<&User> = by_name.values().collect();
+    users.sort_by(|a, b| a.username.cmp(&b.username));
+
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    {
+        let mut file = File::create(&temp_path)?;
+        serde_json::to_writer_pretty(&mut file, &users)?;
+        file.sync_all()?;
+    }
+    fs::rename(&temp_path, path)
+}
@@ -1,14 +1,11 @@
-use serde::{Serialize, Deserialize};
-use chrono::{DateTime, Utc};
+use blog_model::NewPost;
+use serde::Deserialize;
 use std::io;
 
-#[derive(Serialize, Deserialize, Clone, Default)]
-struct BlogPost {
-    id: i32,
-    date: DateTime<Utc>,
-    title: String,
-    body: String,
-    author: String,
+// The part of the server's reply to a login that we need
+#[derive(Deserialize)]
+struct Login {
+    token: String,
 }
 
 fn read_trim() -> String {
@@ -19,21 +16,38 @@ fn read_trim() -> String {
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let mut new_post = BlogPost::default();
+    // Only logged-in users can post, and the post is theirs
+    println!("Enter your username: ");
+    let username = read_trim();
+    println!("Enter your password: ");
+    let password = read_trim();
+
+    let client = reqwest::Client::new();
+    let login = client
+        .post("http://localhost:3001/auth/login")
+        .json(&serde_json::json!({ "username": username, "password": password }))
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<Login>()
+        .await?;
+
+    let mut new_post = NewPost::default();
     println!("Enter the title: ");
     new_post.title = read_trim();
     println!("Enter the body: ");
     new_post.body = read_trim();
-    println!("Enter the author: ");
-    new_post.author = read_trim();
+    println!("Enter the tags, separated by commas: ");
+    new_post.tags = read_trim().split(',').map(|tag| tag.to_string()).collect();
 
     // Post it with Reqwest
-    let client = reqwest::Client::new();
     let new_id = client
         .post("http://localhost:3001/blog/new")
+        .bearer_auth(&login.token)
         .json(&new_post)
         .send()
         .await?
+        .error_for_status()?
         .json::<i32>()
         .await?;
 
@@ -0,0 +1,284 @@
+//! The blog's data types, shared by `blog_client`, `blog_server` and
+//! `blog_server_db` so that every one of them agrees on what goes over the
+//! wire.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// A single blog post, as stored and as returned to clients.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BlogPost {
+    pub id: i32,
+    pub date: DateTime<Utc>,
+    pub title: String,
+    pub body: String,
+    pub author: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Starts at 1 and goes up by one on every update. Send it back when
+    /// changing the post, so two edits can't silently overwrite each other.
+    #[serde(default = "BlogPost::first_version")]
+    pub version: i64,
+}
+
+impl BlogPost {
+    /// The version of a post that has never been updated.
+    pub const FIRST_VERSION: i64 = 1;
+
+    fn first_version() -> i64 {
+        Self::FIRST_VERSION
+    }
+}
+
+/// One page of posts from `/blog/all`. Pass `next_cursor` back as `?cursor=`
+/// to fetch the following page; it's missing on the last page.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PostPage {
+    pub posts: Vec<BlogPost>,
+    pub next_cursor: Option<String>,
+}
+
+/// A post found by `/blog/search`. Higher scores are better matches. The
+/// snippet is an HTML fragment of the post with the matching words wrapped in
+/// `<mark>`; everything else in it is escaped.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SearchHit {
+    pub post: BlogPost,
+    pub score: f64,
+    pub snippet: String,
+}
+
+/// A tag, and how many posts carry it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TagCount {
+    pub tag: String,
+    pub posts: i64,
+}
+
+/// A blog post sent by a client to create (or fully replace) a post. There's
+/// no `id` or `date`: the server picks both, and ignores them if a client
+/// sends them anyway. It also fills in `author` with whoever's logged in.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct NewPost {
+    pub title: String,
+    pub body: String,
+    #[serde(default)]
+    pub author: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// The fields of a blog post that may be changed with PATCH. Anything that
+/// isn't supplied is left alone.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct PostPatch {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
+}
+
+impl PostPatch {
+    /// Apply the patch to a post, changing only the supplied fields.
+    pub fn apply(self, post: &mut BlogPost) {
+        if let Some(date) = self.date {
+            post.date = date;
+        }
+        if let Some(title) = self.title {
+            post.title = title;
+        }
+        if let Some(body) = self.body {
+            post.body = body;
+        }
+        if let Some(author) = self.author {
+            post.author = author;
+        }
+        if let Some(tags) = self.tags {
+            post.tags = tags;
+        }
+    }
+}
+
+/// A full replacement is a patch that sets every field a client may write.
+/// The post keeps its ID and date.
+impl From<NewPost> for PostPatch {
+    fn from(post: NewPost) -> Self {
+        PostPatch {
+            date: None,
+            title: Some(post.title),
+            body: Some(post.body),
+            author: Some(post.author),
+            tags: Some(post.tags),
+        }
+    }
+}
+
+/// A reader's comment on a post. Replies name the comment they answer in
+/// `parent_id`.
+///
+/// Deleting a comment that has replies leaves a tombstone in its place, so
+/// the replies keep their context: `deleted` is set and the author and body
+/// are emptied.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Comment {
+    pub id: i32,
+    pub post_id: i32,
+    pub parent_id: Option<i32>,
+    pub date: DateTime<Utc>,
+    pub author: String,
+    pub body: String,
+    #[serde(default)]
+    pub deleted: bool,
+}
+
+/// A comment sent by a client. Leave out `parent_id` to comment on the post
+/// itself rather than reply to another comment. The server fills in `author`
+/// with whoever's logged in.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct NewComment {
+    #[serde(default)]
+    pub author: String,
+    pub body: String,
+    #[serde(default)]
+    pub parent_id: Option<i32>,
+}
+
+/// A comment with its replies, and their replies, and so on.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CommentThread {
+    #[serde(flatten)]
+    pub comment: Comment,
+    pub replies: Vec<CommentThread>,
+}
+
+impl CommentThread {
+    /// Arrange a post's comments into threads. Comments keep the order they
+    /// were given in, at every level.
+    pub fn build(comments: Vec<Comment>) -> Vec<CommentThread> {
+        let mut replies: HashMap<Option<i32>, Vec<Comment>> = HashMap::new();
+        for comment in comments {
+            replies.entry(comment.parent_id).or_default().push(comment);
+        }
+        Self::children(None, &mut replies)
+    }
+
+    fn children(parent_id: Option<i32>, replies: &mut HashMap<Option<i32>, Vec<Comment>>) -> Vec<CommentThread> {
+        replies
+            .remove(&parent_id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|comment| CommentThread {
+                replies: Self::children(Some(comment.id), replies),
+                comment,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post() -> BlogPost {
+        BlogPost {
+            id: 7,
+            date: "2023-09-26T10:30:00.123Z".parse().unwrap(),
+            title: "Hello".to_string(),
+            body: "World".to_string(),
+            author: "herbert".to_string(),
+            tags: vec!["rust".to_string(), "gophercon".to_string()],
+            version: 3,
+        }
+    }
+
+    #[test]
+    fn blog_post_round_trips() {
+        let post = post();
+        let json = serde_json::to_string(&post).unwrap();
+        let back: BlogPost = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, post);
+        assert_eq!(back.date, post.date);
+        assert_eq!(back.tags, post.tags);
+        assert_eq!(back.version, 3);
+    }
+
+    #[test]
+    fn blog_post_defaults_tags_and_version() {
+        let json = r#"{"id":1,"date":"2023-09-26T10:30:00Z","title":"t","body":"b","author":"a"}"#;
+        let post: BlogPost = serde_json::from_str(json).unwrap();
+        assert!(post.tags.is_empty());
+        assert_eq!(post.version, BlogPost::FIRST_VERSION);
+    }
+
+    #[test]
+    fn new_post_needs_no_id_or_date() {
+        let post: NewPost = serde_json::from_str(r#"{"title":"t","body":"b","tags":["x"]}"#).unwrap();
+        assert_eq!(
+            post,
+            NewPost {
+                title: "t".to_string(),
+                body: "b".to_string(),
+                author: String::new(),
+                tags: vec!["x".to_string()],
+            }
+        );
+    }
+
+    #[test]
+    fn new_post_ignores_id_and_date() {
+        let json = r#"{"id":9,"date":"2023-09-26T10:30:00Z","title":"t","body":"b"}"#;
+        let post: NewPost = serde_json::from_str(json).unwrap();
+        assert_eq!(post.title, "t");
+    }
+
+    #[test]
+    fn patch_leaves_out_missing_fields() {
+        let patch: PostPatch = serde_json::from_str(r#"{"title":"New title","tags":[]}"#).unwrap();
+        assert_eq!(
+            patch,
+            PostPatch {
+                title: Some("New title".to_string()),
+                tags: Some(vec![]),
+                ..PostPatch::default()
+            }
+        );
+        assert_eq!(serde_json::to_string(&patch).unwrap(), r#"{"title":"New title","tags":[]}"#);
+    }
+
+    #[test]
+    fn patch_applies_only_given_fields() {
+        let patch: PostPatch = serde_json::from_str(r#"{"body":"Changed"}"#).unwrap();
+        let mut patched = post();
+        patch.apply(&mut patched);
+        assert_eq!(
+            patched,
+            BlogPost {
+                body: "Changed".to_string(),
+                ..post()
+            }
+        );
+    }
+
+    #[test]
+    fn replacement_keeps_id_and_date() {
+        let replacement = NewPost {
+            title: "T".to_string(),
+            body: "B".to_string(),
+            author: "someone".to_string(),
+            tags: vec![],
+        };
+        let mut replaced = post();
+        PostPatch::from(replacement).apply(&mut replaced);
+        assert_eq!((replaced.id, replaced.date), (post().id, post().date));
+        assert_eq!((replaced.title.as_str(), replaced.body.as_str()), ("T", "B"));
+        assert!(replaced.tags.is_empty());
+    }
+}
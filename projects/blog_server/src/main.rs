@@ -1,93 +1,90 @@
-use axum::{routing::get, Router};
-use std::net::SocketAddr;
-use serde::{Serialize, Deserialize};
-use chrono::{DateTime, Utc};
-
-#[derive(Serialize, Deserialize, Clone)]
-struct BlogPost {
-    id: i32,
-    date: DateTime<Utc>,
-    title: String,
-    body: String,
-    author: String,
-}
-
-use tokio::sync::Mutex;
 use std::sync::Arc;
-use once_cell::sync::Lazy;
+use std::time::Duration;
+use blog_api::PostStore;
+use blog_model::NewPost;
+use server_config::{Config, WalRecovery};
 
-static POSTS: Lazy<Arc<Mutex<Vec<BlogPost>>>> = Lazy::new(|| {
-    Arc::new(Mutex::new(vec![
-        BlogPost {
-            id: 1,
-            date: Utc::now(),
-            title: "A Tale of Two Cities".to_string(),
-            body: "It was the best of times, it was the worst of times.".to_string(),
-            author: "Dickens".to_string(),
-        },
-        BlogPost {
-            id: 2,
-            date: Utc::now(),
-            title: "Moby Dick".to_string(),
-            body: "Call me Ishmael.".to_string(),
-            author: "Melville".to_string(),
-        },
-    ]))
-});
+mod memory_store;
+mod persistence;
+mod search_index;
+#[cfg(test)]
+mod tests;
+use memory_store::MemoryStore;
+use persistence::RecoveryMode;
+
+// How often to write a snapshot of the store, if it's being saved
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(300);
 
 #[tokio::main]
 async fn main() {
-    // Bind the default route to the function `say_hello_text`
-    use axum::routing::post;
-    let app = Router::new()
-        .route("/", get(say_hello_text))
-        .route("/blog/all", get(all_posts))
-        .route("/blog/:id", get(get_post))
-        .route("/blog/new", post(new_post));
+    // Read the settings from the config file, environment and command line
+    let config = Config::load();
+    config.init_logging();
 
-    // Listen on localhost, port 3000
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3001));
+    // Keep the posts in memory. If there's a data directory, they're saved
+    // there too and reloaded on restart.
+    let store = match &config.data_dir {
+        Some(dir) => {
+            let mode = match config.wal_recovery {
+                WalRecovery::Strict => RecoveryMode::Strict,
+                WalRecovery::Tolerant => RecoveryMode::TolerateCorruptTail,
+            };
+            MemoryStore::open(dir, mode).expect("Unable to load the saved posts")
+        }
+        None => MemoryStore::new(),
+    };
+    let store = Arc::new(store);
 
-    // Start the server
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
-}
+    // Start with a couple of examples
+    if store.is_new() {
+        for post in initial_posts() {
+            store.create(post).await.expect("Unable to add the initial posts");
+        }
+    }
 
-// Return a static string
-async fn say_hello_text() -> &'static str {
-    "Hello, world!"
-}
+    // Snapshot the store now and then, so the log doesn't grow forever
+    let snapshots = store.clone();
+    tokio::spawn(async move {
+        let mut timer = tokio::time::interval(SNAPSHOT_INTERVAL);
+        loop {
+            timer.tick().await;
+            if let Err(e) = snapshots.compact().await {
+                tracing::error!("Unable to write a snapshot: {e}");
+            }
+        }
+    });
 
-// Return all blog posts
-use axum::Json;
-async fn all_posts() -> Json<Vec<BlogPost>> {
-    // Lock the mutex
-    let lock = POSTS.lock().await;
-    Json(lock.to_vec())
-}
+    // All of the routes live in `blog_api`, shared with `blog_server_db`, as
+    // does setting up the rate limits and logins from the config
+    let app = blog_api::router_from_config(store.clone(), &config).expect("Unable to set up the server");
 
-// Return a single blog post by ID number
-use axum::extract::Path;
-async fn get_post(Path(id) : Path<i32>) -> Json<BlogPost> {
-    let lock = POSTS.lock().await;
-    let post = lock.iter().find(|post| post.id == id).cloned().unwrap();
-    Json(post)
+    // Serve on every configured address (by default, localhost port 3001)
+    // until we're asked to stop, then save everything before exiting
+    let drain_timeout = Duration::from_secs(config.shutdown_timeout);
+    let served = blog_api::serve(app, &config.listen, drain_timeout).await;
+    if let Err(e) = store.close().await {
+        tracing::error!("Unable to save the posts: {e}");
+        std::process::exit(1);
+    }
+    if let Err(message) = served {
+        tracing::error!("{message}");
+        std::process::exit(1);
+    }
 }
 
-// Add a blog entry
-async fn new_post(Json(post) : Json<BlogPost>) -> Json<i32> {
-    let mut post = post; // Move it into a mutable variable
-    let mut lock = POSTS.lock().await; // Lock the mutex
-
-    // Find the maximum ID # and add one
-    let new_id = lock.iter().max_by(|a,b| a.id.cmp(&b.id)).unwrap().id + 1;
-    post.id = new_id;
-
-    // Add the post
-    lock.push(post);
-
-    // Return the new ID number
-    Json(new_id)
-}
\ No newline at end of file
+fn initial_posts() -> Vec<NewPost> {
+    vec![
+        NewPost {
+            title: "A Tale of Two Cities".to_string(),
+            body: "It was the best of times, it was the worst of times.".to_string(),
+            author: "Dickens".to_string(),
+            tags: vec!["classics".to_string()],
+        },
+        NewPost {
+            title: "Moby Dick".to_string(),
+            body: "Call me Ishmael.".to_string(),
+            author: "Melville".to_string(),
+            tags: vec!["classics".to_string(), "whales".to_string()],
+        },
+    ]
+}
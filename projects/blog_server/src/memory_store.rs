@@ -0,0 +1,458 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use blog_api::{ApiError, ListQuery, MetricsText, PostStore, SearchQuery, StoreVersion};
+use blog_model::{BlogPost, Comment, NewComment, NewPost, PostPage, PostPatch, SearchHit, TagCount};
+use chrono::{DateTime, Utc};
+use tokio::sync::{OwnedRwLockWriteGuard, RwLock};
+
+use crate::persistence::{LogEntry, Persistence, RecoveryMode, Snapshot};
+use crate::search_index::{self, SearchIndex};
+
+/// Keeps blog posts in a `HashMap`, keyed by ID, with a search index beside
+/// it. Made with `new`, everything is lost when the server stops; made with
+/// `open`, every change is logged to disk first and reloaded on restart.
+pub struct MemoryStore {
+    // Shared so a change can carry its write lock to a blocking thread
+    posts: Arc<RwLock<Posts>>,
+    // The next ID to hand out. IDs only ever go up, so a deleted post's ID is
+    // never given to a new post - even if the store is emptied.
+    next_id: AtomicI32,
+    next_comment_id: AtomicI32,
+}
+
+// The posts and their index live behind the same lock, so the index can
+// never disagree with the posts.
+struct Posts {
+    by_id: HashMap<i32, BlogPost>,
+    index: SearchIndex,
+    // Each post's comments, oldest first
+    comments: HashMap<i32, Vec<Comment>>,
+    // Where changes are saved, if anywhere. It's behind the same lock so the
+    // log is in the same order as the changes.
+    log: Option<Persistence>,
+    // Bumped on every change. It starts again from zero on restart, but
+    // `modified` makes the pair unique.
+    version: i64,
+    modified: DateTime<Utc>,
+}
+
+impl Posts {
+    fn new(by_id: HashMap<i32, BlogPost>, comments: HashMap<i32, Vec<Comment>>, log: Option<Persistence>) -> Self {
+        let mut index = SearchIndex::default();
+        for post in by_id.values() {
+            index.add(post);
+        }
+        Self {
+            by_id,
+            index,
+            comments,
+            log,
+            version: 0,
+            modified: Utc::now(),
+        }
+    }
+
+    // Note a change, saving it before it's made in memory so that if saving
+    // fails the store is left as it was.
+    fn record(&mut self, entry: LogEntry) -> io::Result<()> {
+        if let Some(log) = &mut self.log {
+            log.append(&entry)?;
+        }
+        self.version += 1;
+        self.modified = Utc::now();
+        Ok(())
+    }
+
+    // Compact if the log has grown long. A failure here isn't the caller's
+    // problem - their change is already safely in the log.
+    fn compact_if_needed(&mut self, next_ids: NextIds) {
+        if self.log.as_ref().is_some_and(Persistence::wants_compaction) {
+            if let Err(e) = self.write_snapshot(next_ids) {
+                tracing::error!("Unable to write a snapshot: {e}");
+            }
+        }
+    }
+
+    fn write_snapshot(&mut self, (next_id, next_comment_id): NextIds) -> io::Result<()> {
+        let snapshot = Snapshot {
+            next_id,
+            next_comment_id,
+            posts: self.by_id.clone(),
+            comments: self.comments.clone(),
+        };
+        match &mut self.log {
+            Some(log) => log.compact(&snapshot),
+            None => Ok(()),
+        }
+    }
+}
+
+// The next post and comment IDs, for a snapshot
+type NextIds = (i32, i32);
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self {
+            posts: Arc::new(RwLock::new(Posts::new(HashMap::new(), HashMap::new(), None))),
+            next_id: AtomicI32::new(1),
+            next_comment_id: AtomicI32::new(1),
+        }
+    }
+
+    /// Load the store saved in `dir`, and keep saving every change there.
+    pub fn open(dir: &Path, mode: RecoveryMode) -> io::Result<Self> {
+        let (log, saved) = Persistence::open(dir, mode)?;
+        Ok(Self {
+            posts: Arc::new(RwLock::new(Posts::new(saved.posts, saved.comments, Some(log)))),
+            next_id: AtomicI32::new(saved.next_id),
+            next_comment_id: AtomicI32::new(saved.next_comment_id),
+        })
+    }
+
+    /// Has this store never held a post?
+    pub fn is_new(&self) -> bool {
+        self.next_id.load(Ordering::Relaxed) == 1
+    }
+
+    /// Write a snapshot of the store and empty the log, if anything has
+    /// changed since the last one.
+    pub async fn compact(&self) -> io::Result<()> {
+        let mut lock = self.posts.clone().write_owned().await;
+        if !lock.log.as_ref().is_some_and(Persistence::has_changes) {
+            return Ok(());
+        }
+        let next_ids = self.next_ids();
+        tokio::task::spawn_blocking(move || lock.write_snapshot(next_ids))
+            .await
+            .map_err(io::Error::other)?
+    }
+
+    // Make a change: log it, make it in memory with `apply`, then compact the
+    // log if it's grown long. Logging waits for the disk, so when there's a
+    // log it's all done on a blocking thread rather than holding up the async
+    // ones. The write lock goes with it, keeping the log in the same order as
+    // the changes, and it runs to the end even if the caller stops waiting,
+    // so a change is never logged but not made.
+    async fn change<T: Send + 'static>(
+        &self,
+        mut lock: OwnedRwLockWriteGuard<Posts>,
+        entry: LogEntry,
+        apply: impl FnOnce(&mut Posts) -> T + Send + 'static,
+    ) -> Result<T, ApiError> {
+        let logged = lock.log.is_some();
+        let next_ids = self.next_ids();
+        let change = move || {
+            lock.record(entry)?;
+            let result = apply(&mut lock);
+            lock.compact_if_needed(next_ids);
+            Ok(result)
+        };
+        let result: io::Result<T> = match logged {
+            true => tokio::task::spawn_blocking(change).await.map_err(ApiError::database)?,
+            false => change(),
+        };
+        result.map_err(ApiError::database)
+    }
+
+    fn next_ids(&self) -> NextIds {
+        (self.next_id.load(Ordering::Relaxed), self.next_comment_id.load(Ordering::Relaxed))
+    }
+}
+
+#[async_trait]
+impl PostStore for MemoryStore {
+    async fn list(&self, query: &ListQuery) -> Result<PostPage, ApiError> {
+        let lock = self.posts.read().await;
+
+        // Sort references rather than posts, so only the page itself is cloned
+        let mut matches: Vec<&BlogPost> = lock
+            .by_id
+            .values()
+            .filter(|post| query.matches(post) && query.is_after_cursor(post))
+            .collect();
+        matches.sort_by(|a, b| query.compare(a, b));
+
+        let posts: Vec<BlogPost> = matches.iter().take(query.limit).map(|post| (*post).clone()).collect();
+        let next_cursor = if matches.len() > query.limit {
+            posts.last().map(|post| query.cursor_after(post))
+        } else {
+            None
+        };
+        Ok(PostPage { posts, next_cursor })
+    }
+
+    async fn get(&self, id: i32) -> Result<Option<BlogPost>, ApiError> {
+        Ok(self.posts.read().await.by_id.get(&id).cloned())
+    }
+
+    async fn create(&self, post: NewPost) -> Result<BlogPost, ApiError> {
+        let post = BlogPost {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            date: Utc::now(),
+            title: post.title,
+            body: post.body,
+            author: post.author,
+            tags: post.tags,
+            version: BlogPost::FIRST_VERSION,
+        };
+        let lock = self.posts.clone().write_owned().await;
+        let entry = LogEntry::PutPost { post: post.clone() };
+        let created = post.clone();
+        self.change(lock, entry, move |posts| {
+            posts.index.add(&created);
+            posts.by_id.insert(created.id, created);
+        })
+        .await?;
+        Ok(post)
+    }
+
+    async fn update(&self, id: i32, patch: PostPatch, expected_version: i64) -> Result<Option<BlogPost>, ApiError> {
+        let lock = self.posts.clone().write_owned().await;
+        let Some(mut post) = lock.by_id.get(&id).cloned() else {
+            return Ok(None);
+        };
+        if post.version != expected_version {
+            return Err(ApiError::PreconditionFailed);
+        }
+        patch.apply(&mut post);
+        post.version += 1;
+        let entry = LogEntry::PutPost { post: post.clone() };
+        let updated = post.clone();
+        self.change(lock, entry, move |posts| {
+            posts.index.remove(id);
+            posts.index.add(&updated);
+            posts.by_id.insert(id, updated);
+        })
+        .await?;
+        Ok(Some(post))
+    }
+
+    async fn delete(&self, id: i32, expected_version: i64) -> Result<bool, ApiError> {
+        let lock = self.posts.clone().write_owned().await;
+        match lock.by_id.get(&id) {
+            None => return Ok(false),
+            Some(post) if post.version != expected_version => return Err(ApiError::PreconditionFailed),
+            Some(_) => {}
+        }
+        self.change(lock, LogEntry::DeletePost { id }, move |posts| {
+            posts.index.remove(id);
+            posts.comments.remove(&id);
+            posts.by_id.remove(&id);
+        })
+        .await?;
+        Ok(true)
+    }
+
+    async fn search(&self, query: &SearchQuery, limit: usize) -> Result<Vec<SearchHit>, ApiError> {
+        let lock = self.posts.read().await;
+        Ok(lock
+            .index
+            .search(query)
+            .into_iter()
+            .take(limit)
+            .filter_map(|(id, score)| {
+                let post = lock.by_id.get(&id)?;
+                Some(SearchHit {
+                    post: post.clone(),
+                    score,
+                    snippet: search_index::snippet(post, query),
+                })
+            })
+            .collect())
+    }
+
+    async fn tags(&self) -> Result<Vec<TagCount>, ApiError> {
+        let lock = self.posts.read().await;
+        let mut counts: BTreeMap<&str, i64> = BTreeMap::new();
+        for tag in lock.by_id.values().flat_map(|post| &post.tags) {
+            *counts.entry(tag).or_default() += 1;
+        }
+        Ok(counts
+            .into_iter()
+            .map(|(tag, posts)| TagCount { tag: tag.to_string(), posts })
+            .collect())
+    }
+
+    async fn comments(&self, post_id: i32) -> Result<Option<Vec<Comment>>, ApiError> {
+        let lock = self.posts.read().await;
+        if !lock.by_id.contains_key(&post_id) {
+            return Ok(None);
+        }
+        Ok(Some(lock.comments.get(&post_id).cloned().unwrap_or_default()))
+    }
+
+    async fn add_comment(&self, post_id: i32, comment: NewComment) -> Result<Option<Comment>, ApiError> {
+        let lock = self.posts.clone().write_owned().await;
+        if !lock.by_id.contains_key(&post_id) {
+            return Ok(None);
+        }
+        let mut comments = lock.comments.get(&post_id).cloned().unwrap_or_default();
+        if let Some(parent_id) = comment.parent_id {
+            if !comments.iter().any(|c| c.id == parent_id && !c.deleted) {
+                return Err(ApiError::BadRequest(format!("There is no comment {parent_id} on this post to reply to")));
+            }
+        }
+
+        let comment = Comment {
+            id: self.next_comment_id.fetch_add(1, Ordering::Relaxed),
+            post_id,
+            parent_id: comment.parent_id,
+            date: Utc::now(),
+            author: comment.author,
+            body: comment.body,
+            deleted: false,
+        };
+        comments.push(comment.clone());
+        let entry = LogEntry::SetComments { post_id, comments: comments.clone() };
+        self.change(lock, entry, move |posts| posts.comments.insert(post_id, comments)).await?;
+        Ok(Some(comment))
+    }
+
+    async fn delete_comment(&self, post_id: i32, comment_id: i32) -> Result<bool, ApiError> {
+        let lock = self.posts.clone().write_owned().await;
+        let Some(mut comments) = lock.comments.get(&post_id).cloned() else {
+            return Ok(false);
+        };
+        let Some(index) = comments.iter().position(|c| c.id == comment_id) else {
+            return Ok(false);
+        };
+
+        // Keep a tombstone if anyone has replied
+        if comments.iter().any(|c| c.parent_id == Some(comment_id)) {
+            let comment = &mut comments[index];
+            comment.deleted = true;
+            comment.author.clear();
+            comment.body.clear();
+        } else {
+            // Otherwise remove it, and any tombstones that only existed for it
+            let mut parent_id = comments.remove(index).parent_id;
+            while let Some(id) = parent_id {
+                let has_replies = comments.iter().any(|c| c.parent_id == Some(id));
+                match comments.iter().position(|c| c.id == id && c.deleted) {
+                    Some(index) if !has_replies => parent_id = comments.remove(index).parent_id,
+                    _ => break,
+                }
+            }
+        }
+
+        let entry = LogEntry::SetComments { post_id, comments: comments.clone() };
+        self.change(lock, entry, move |posts| posts.comments.insert(post_id, comments)).await?;
+        Ok(true)
+    }
+
+    async fn version(&self) -> Result<StoreVersion, ApiError> {
+        let lock = self.posts.read().await;
+        Ok(StoreVersion {
+            version: lock.version,
+            modified: lock.modified,
+        })
+    }
+
+    async fn write_metrics(&self, out: &mut MetricsText) -> Result<(), ApiError> {
+        let lock = self.posts.read().await;
+        let comments = lock.comments.values().flatten().filter(|comment| !comment.deleted).count();
+        out.gauge("blog_posts", "Posts in the store.", lock.by_id.len());
+        out.gauge("blog_comments", "Comments in the store, not counting deleted ones.", comments);
+        Ok(())
+    }
+
+    async fn close(&self) -> Result<(), ApiError> {
+        // Every change is already in the log; a snapshot just means the next
+        // start doesn't have to replay it
+        self.compact().await.map_err(ApiError::database)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use blog_api::store_tests;
+
+    use super::*;
+
+    fn new_post(title: &str) -> NewPost {
+        NewPost {
+            title: title.to_string(),
+            body: format!("All about {title}"),
+            author: "herbert".to_string(),
+            tags: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn saved_changes_survive_a_restart() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = MemoryStore::open(dir.path(), RecoveryMode::Strict).unwrap();
+        assert!(store.is_new());
+        let whales = store.create(new_post("Whales")).await.unwrap();
+        let ships = store.create(new_post("Ships")).await.unwrap();
+        let comment = NewComment {
+            author: "ashley".to_string(),
+            body: "Nice".to_string(),
+            parent_id: None,
+        };
+        store.add_comment(ships.id, comment).await.unwrap().unwrap();
+        store.compact().await.unwrap();
+
+        let patch = PostPatch {
+            title: Some("Big whales".to_string()),
+            ..PostPatch::default()
+        };
+        store.update(whales.id, patch, whales.version).await.unwrap().unwrap();
+        store.create(new_post("Gophers")).await.unwrap();
+        assert!(store.delete(ships.id, ships.version).await.unwrap());
+        let version = store.version().await.unwrap().version;
+        drop(store);
+
+        let store = MemoryStore::open(dir.path(), RecoveryMode::Strict).unwrap();
+        assert!(!store.is_new());
+        let whales = store.get(whales.id).await.unwrap().unwrap();
+        assert_eq!((whales.title.as_str(), whales.version), ("Big whales", 2));
+        assert_eq!(store.get(ships.id).await.unwrap(), None);
+        assert_eq!(store.comments(ships.id).await.unwrap(), None);
+        assert_eq!(store.create(new_post("Boats")).await.unwrap().id, 4);
+        assert_eq!(version, 6);
+    }
+
+    #[tokio::test]
+    async fn lists() {
+        store_tests::lists(&MemoryStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn keyless_cursors_sort_last() {
+        store_tests::keyless_cursors_sort_last(&MemoryStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn searches() {
+        store_tests::searches(&MemoryStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn tags() {
+        store_tests::tags(&MemoryStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn comments() {
+        store_tests::comments(&MemoryStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn versions() {
+        store_tests::versions(&MemoryStore::new()).await;
+    }
+
+    #[tokio::test]
+    async fn unsaved_stores_change_too() {
+        let store = MemoryStore::new();
+        let post = store.create(new_post("Whales")).await.unwrap();
+        assert_eq!(store.get(post.id).await.unwrap(), Some(post));
+        assert_eq!(store.version().await.unwrap().version, 1);
+    }
+}
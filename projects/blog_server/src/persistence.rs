@@ -0,0 +1,455 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use blog_model::{BlogPost, Comment};
+use serde::{Deserialize, Serialize};
+
+const SNAPSHOT_FILE: &str = "snapshot.json";
+const LOG_FILE: &str = "wal.jsonl";
+
+// Write a fresh snapshot (and empty the log) after this many log entries.
+const COMPACT_AFTER: usize = 1000;
+
+/// One change to the store, as written to the write-ahead log. Entries hold
+/// the state after the change, so replaying them is just overwriting.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum LogEntry {
+    PutPost { post: BlogPost },
+    DeletePost { id: i32 },
+    SetComments { post_id: i32, comments: Vec<Comment> },
+}
+
+/// Everything in the store at one moment.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Snapshot {
+    pub next_id: i32,
+    pub next_comment_id: i32,
+    pub posts: HashMap<i32, BlogPost>,
+    pub comments: HashMap<i32, Vec<Comment>>,
+}
+
+impl Default for Snapshot {
+    fn default() -> Self {
+        Self {
+            next_id: 1,
+            next_comment_id: 1,
+            posts: HashMap::new(),
+            comments: HashMap::new(),
+        }
+    }
+}
+
+impl Snapshot {
+    fn apply(&mut self, entry: LogEntry) {
+        match entry {
+            LogEntry::PutPost { post } => {
+                self.next_id = self.next_id.max(post.id + 1);
+                self.posts.insert(post.id, post);
+            }
+            LogEntry::DeletePost { id } => {
+                self.posts.remove(&id);
+                self.comments.remove(&id);
+            }
+            LogEntry::SetComments { post_id, comments } => {
+                if let Some(last) = comments.iter().map(|c| c.id).max() {
+                    self.next_comment_id = self.next_comment_id.max(last + 1);
+                }
+                self.comments.insert(post_id, comments);
+            }
+        }
+    }
+}
+
+/// What to do when the last line of the log can't be read, which is what a
+/// crash part-way through a write leaves behind.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecoveryMode {
+    /// Refuse to start.
+    Strict,
+    /// Drop the damaged line and carry on. Only the final line may be
+    /// damaged; anything earlier is still an error.
+    TolerateCorruptTail,
+}
+
+/// Keeps the in-memory store on disk: a snapshot of everything, plus a log of
+/// every change since, one JSON object per line.
+pub struct Persistence {
+    dir: PathBuf,
+    log: File,
+    entries: usize,
+}
+
+impl Persistence {
+    /// Open (or create) the data directory and rebuild the store from it: the
+    /// last snapshot, with the log replayed over the top.
+    pub fn open(dir: &Path, mode: RecoveryMode) -> io::Result<(Self, Snapshot)> {
+        fs::create_dir_all(dir)?;
+
+        let snapshot_path = dir.join(SNAPSHOT_FILE);
+        let mut state = if snapshot_path.exists() {
+            serde_json::from_reader(BufReader::new(File::open(&snapshot_path)?))
+                .map_err(|e| invalid_data(format!("{} is damaged: {e}", snapshot_path.display())))?
+        } else {
+            Snapshot::default()
+        };
+
+        let log_path = dir.join(LOG_FILE);
+        let mut log = OpenOptions::new().create(true).read(true).append(true).open(&log_path)?;
+        let (entries, good_length) = replay(&log, &log_path, &mut state, mode)?;
+
+        // Cut off a damaged tail, and make sure the next entry starts on a
+        // line of its own
+        if good_length < log.metadata()?.len() {
+            tracing::warn!("Dropping a damaged entry from the end of {}", log_path.display());
+            log.set_len(good_length)?;
+        }
+        if good_length > 0 {
+            log.seek(SeekFrom::Start(good_length - 1))?;
+            let mut last = [0u8];
+            io::Read::read_exact(&mut log, &mut last)?;
+            if last[0] != b'\n' {
+                log.write_all(b"\n")?;
+            }
+        }
+        log.sync_all()?;
+
+        let persistence = Self {
+            dir: dir.to_path_buf(),
+            log,
+            entries,
+        };
+        Ok((persistence, state))
+    }
+
+    /// Durably record a change. Call this before changing the store, so a
+    /// failed write leaves memory and disk in agreement.
+    pub fn append(&mut self, entry: &LogEntry) -> io::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        self.log.write_all(&line)?;
+        self.log.sync_data()?;
+        self.entries += 1;
+        Ok(())
+    }
+
+    /// Has anything been logged since the last snapshot?
+    pub fn has_changes(&self) -> bool {
+        self.entries > 0
+    }
+
+    /// Has the log grown enough that it's worth compacting?
+    pub fn wants_compaction(&self) -> bool {
+        self.entries >= COMPACT_AFTER
+    }
+
+    /// Write a snapshot of the whole store and empty the log. A crash at any
+    /// point leaves files that `open` rebuilds the same store from.
+    ///
+    /// The snapshot is first written to `snapshot.json.tmp`. A crash while
+    /// that's happening leaves the old snapshot and the whole log as they
+    /// were, beside a temporary file that `open` ignores and the next
+    /// compaction overwrites.
+    ///
+    /// The temporary file is then renamed over `snapshot.json`. A crash after
+    /// that, but before the log is emptied, leaves the new snapshot and the
+    /// whole old log. `open` replays the log over the snapshot, and as each
+    /// entry holds the state after its change, replaying changes the snapshot
+    /// already has just writes them again, ending where the snapshot was.
+    ///
+    /// Emptying the log either happens or it doesn't, leaving the new
+    /// snapshot with an empty log or with the whole old one.
+    pub fn compact(&mut self, state: &Snapshot) -> io::Result<()> {
+        let snapshot_path = self.dir.join(SNAPSHOT_FILE);
+        let temp_path = self.dir.join(format!("{SNAPSHOT_FILE}.tmp"));
+        {
+            let mut file = File::create(&temp_path)?;
+            serde_json::to_writer(&mut file, state)?;
+            file.sync_all()?;
+        }
+        fs::rename(&temp_path, &snapshot_path)?;
+        File::open(&self.dir)?.sync_all()?;
+
+        self.log.set_len(0)?;
+        self.log.sync_all()?;
+        self.entries = 0;
+        Ok(())
+    }
+}
+
+// Apply every readable log entry to `state`. Returns how many entries there
+// were, and the length of the log up to the end of the last good one.
+fn replay(log: &File, path: &Path, state: &mut Snapshot, mode: RecoveryMode) -> io::Result<(usize, u64)> {
+    let mut reader = BufReader::new(log);
+    reader.seek(SeekFrom::Start(0))?;
+
+    let mut entries = 0;
+    let mut good_length = 0;
+    let mut line_number = 0;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let read = reader.read_until(b'\n', &mut line)?;
+        if read == 0 {
+            return Ok((entries, good_length));
+        }
+        line_number += 1;
+
+        let text = line.strip_suffix(b"\n").unwrap_or(&line);
+        if text.iter().all(u8::is_ascii_whitespace) {
+            good_length += read as u64;
+            continue;
+        }
+        match serde_json::from_slice::<LogEntry>(text) {
+            Ok(entry) => {
+                state.apply(entry);
+                entries += 1;
+                good_length += read as u64;
+            }
+            Err(e) => {
+                let at_end = reader.fill_buf()?.is_empty();
+                if at_end && mode == RecoveryMode::TolerateCorruptTail {
+                    return Ok((entries, good_length));
+                }
+                return Err(invalid_data(format!("{} line {line_number} is damaged: {e}", path.display())));
+            }
+        }
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(id: i32, title: &str) -> BlogPost {
+        BlogPost {
+            id,
+            date: chrono::Utc::now(),
+            title: title.to_string(),
+            body: format!("All about {title}"),
+            author: "herbert".to_string(),
+            tags: vec![],
+            version: BlogPost::FIRST_VERSION,
+        }
+    }
+
+    fn comment(id: i32, post_id: i32) -> Comment {
+        Comment {
+            id,
+            post_id,
+            parent_id: None,
+            date: chrono::Utc::now(),
+            author: "ashley".to_string(),
+            body: "Nice".to_string(),
+            deleted: false,
+        }
+    }
+
+    fn log(persistence: &mut Persistence, entries: impl IntoIterator<Item = LogEntry>) {
+        for entry in entries {
+            persistence.append(&entry).unwrap();
+        }
+    }
+
+    fn titles(state: &Snapshot) -> Vec<&str> {
+        let mut titles: Vec<&str> = state.posts.values().map(|post| post.title.as_str()).collect();
+        titles.sort();
+        titles
+    }
+
+    fn log_path(dir: &tempfile::TempDir) -> PathBuf {
+        dir.path().join(LOG_FILE)
+    }
+
+    #[test]
+    fn replays_the_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut persistence, state) = Persistence::open(dir.path(), RecoveryMode::Strict).unwrap();
+        assert!(state.posts.is_empty());
+        log(
+            &mut persistence,
+            [
+                LogEntry::PutPost { post: post(1, "Whales") },
+                LogEntry::PutPost { post: post(2, "Ships") },
+                LogEntry::PutPost { post: post(3, "Gophers") },
+                LogEntry::SetComments { post_id: 1, comments: vec![comment(1, 1), comment(2, 1)] },
+                LogEntry::SetComments { post_id: 3, comments: vec![comment(3, 3)] },
+                LogEntry::DeletePost { id: 3 },
+            ],
+        );
+        drop(persistence);
+
+        let (persistence, state) = Persistence::open(dir.path(), RecoveryMode::Strict).unwrap();
+        assert_eq!(titles(&state), ["Ships", "Whales"]);
+        assert_eq!(state.comments.keys().collect::<Vec<_>>(), [&1]);
+        assert_eq!((state.next_id, state.next_comment_id), (4, 4));
+        assert!(persistence.has_changes());
+    }
+
+    #[test]
+    fn a_truncated_last_line_is_dropped_only_if_tolerated() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut persistence, _) = Persistence::open(dir.path(), RecoveryMode::Strict).unwrap();
+        log(&mut persistence, [LogEntry::PutPost { post: post(1, "Whales") }]);
+        drop(persistence);
+        // A crash part-way through writing the second entry
+        let whole = fs::read(log_path(&dir)).unwrap();
+        let mut damaged = whole.clone();
+        damaged.extend_from_slice(&whole[..whole.len() / 2]);
+        fs::write(log_path(&dir), &damaged).unwrap();
+
+        let error = Persistence::open(dir.path(), RecoveryMode::Strict).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(fs::read(log_path(&dir)).unwrap(), damaged, "strict recovery shouldn't touch the log");
+
+        let (mut persistence, state) = Persistence::open(dir.path(), RecoveryMode::TolerateCorruptTail).unwrap();
+        assert_eq!(titles(&state), ["Whales"]);
+        assert_eq!(fs::read(log_path(&dir)).unwrap(), whole);
+
+        // and the log carries on from the last good entry
+        log(&mut persistence, [LogEntry::PutPost { post: post(2, "Ships") }]);
+        drop(persistence);
+        let (_, state) = Persistence::open(dir.path(), RecoveryMode::Strict).unwrap();
+        assert_eq!(titles(&state), ["Ships", "Whales"]);
+    }
+
+    #[test]
+    fn a_missing_final_newline_is_added() {
+        let dir = tempfile::tempdir().unwrap();
+        let line = serde_json::to_string(&LogEntry::PutPost { post: post(1, "Whales") }).unwrap();
+        fs::write(log_path(&dir), &line).unwrap();
+
+        let (mut persistence, state) = Persistence::open(dir.path(), RecoveryMode::Strict).unwrap();
+        assert_eq!(titles(&state), ["Whales"]);
+        log(&mut persistence, [LogEntry::PutPost { post: post(2, "Ships") }]);
+        drop(persistence);
+        let (_, state) = Persistence::open(dir.path(), RecoveryMode::Strict).unwrap();
+        assert_eq!(titles(&state), ["Ships", "Whales"]);
+    }
+
+    #[test]
+    fn corruption_before_the_last_line_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut persistence, _) = Persistence::open(dir.path(), RecoveryMode::Strict).unwrap();
+        log(
+            &mut persistence,
+            [
+                LogEntry::PutPost { post: post(1, "Whales") },
+                LogEntry::PutPost { post: post(2, "Ships") },
+                LogEntry::PutPost { post: post(3, "Gophers") },
+            ],
+        );
+        drop(persistence);
+        let text = fs::read_to_string(log_path(&dir)).unwrap();
+        let mut lines: Vec<&str> = text.lines().collect();
+        lines[1] = r#"{"op":"put_post","post":{"id":2,"#;
+        fs::write(log_path(&dir), lines.join("\n") + "\n").unwrap();
+
+        for mode in [RecoveryMode::Strict, RecoveryMode::TolerateCorruptTail] {
+            let error = Persistence::open(dir.path(), mode).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{mode:?}");
+            assert!(error.to_string().contains("line 2"), "{error}");
+        }
+    }
+
+    #[test]
+    fn recovers_from_a_snapshot_and_the_log_after_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut persistence, mut state) = Persistence::open(dir.path(), RecoveryMode::Strict).unwrap();
+        for entry in [
+            LogEntry::PutPost { post: post(1, "Whales") },
+            LogEntry::PutPost { post: post(2, "Ships") },
+            LogEntry::SetComments { post_id: 2, comments: vec![comment(1, 2)] },
+        ] {
+            persistence.append(&entry).unwrap();
+            state.apply(entry);
+        }
+        persistence.compact(&state).unwrap();
+        assert!(!persistence.has_changes());
+        assert_eq!(fs::metadata(log_path(&dir)).unwrap().len(), 0);
+        assert!(!dir.path().join(format!("{SNAPSHOT_FILE}.tmp")).exists());
+
+        log(
+            &mut persistence,
+            [LogEntry::DeletePost { id: 1 }, LogEntry::PutPost { post: post(3, "Gophers") }],
+        );
+        drop(persistence);
+
+        let (_, state) = Persistence::open(dir.path(), RecoveryMode::Strict).unwrap();
+        assert_eq!(titles(&state), ["Gophers", "Ships"]);
+        assert_eq!(state.comments[&2].len(), 1);
+        assert_eq!((state.next_id, state.next_comment_id), (4, 2));
+    }
+
+    #[test]
+    fn a_damaged_snapshot_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join(SNAPSHOT_FILE), "{\"next_id\":").unwrap();
+        for mode in [RecoveryMode::Strict, RecoveryMode::TolerateCorruptTail] {
+            let error = Persistence::open(dir.path(), mode).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{mode:?}");
+        }
+    }
+
+    // The store as JSON, which is easier to compare
+    fn json(state: &Snapshot) -> serde_json::Value {
+        serde_json::to_value(state).unwrap()
+    }
+
+    // Log changes that include deleting a post with comments, so replaying
+    // them again would bring it back if anything did
+    fn log_changes(dir: &tempfile::TempDir) -> Snapshot {
+        let (mut persistence, mut state) = Persistence::open(dir.path(), RecoveryMode::Strict).unwrap();
+        for entry in [
+            LogEntry::PutPost { post: post(1, "Whales") },
+            LogEntry::PutPost { post: post(2, "Ships") },
+            LogEntry::SetComments { post_id: 2, comments: vec![comment(1, 2)] },
+            LogEntry::PutPost { post: post(1, "Big whales") },
+            LogEntry::DeletePost { id: 2 },
+        ] {
+            persistence.append(&entry).unwrap();
+            state.apply(entry);
+        }
+        state
+    }
+
+    #[test]
+    fn a_crash_while_writing_a_snapshot_keeps_the_old_one() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = log_changes(&dir);
+        let temp_path = dir.path().join(format!("{SNAPSHOT_FILE}.tmp"));
+        fs::write(&temp_path, "{\"next_id\":").unwrap();
+
+        let (mut persistence, recovered) = Persistence::open(dir.path(), RecoveryMode::Strict).unwrap();
+        assert_eq!(json(&recovered), json(&state));
+        persistence.compact(&recovered).unwrap();
+        assert!(!temp_path.exists());
+    }
+
+    #[test]
+    fn a_crash_before_the_log_is_emptied_replays_it_over_the_new_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = log_changes(&dir);
+        // What `compact` leaves once it's renamed the snapshot into place
+        fs::write(dir.path().join(SNAPSHOT_FILE), serde_json::to_vec(&state).unwrap()).unwrap();
+        assert!(fs::metadata(log_path(&dir)).unwrap().len() > 0);
+
+        let (mut persistence, recovered) = Persistence::open(dir.path(), RecoveryMode::Strict).unwrap();
+        assert_eq!(json(&recovered), json(&state));
+        assert_eq!(titles(&recovered), ["Big whales"]);
+        assert!(recovered.comments.is_empty());
+
+        // and it all carries on from there
+        log(&mut persistence, [LogEntry::PutPost { post: post(3, "Gophers") }]);
+        drop(persistence);
+        let (_, recovered) = Persistence::open(dir.path(), RecoveryMode::Strict).unwrap();
+        assert_eq!(titles(&recovered), ["Big whales", "Gophers"]);
+        assert_eq!((recovered.next_id, recovered.next_comment_id), (4, 2));
+    }
+}
@@ -0,0 +1,453 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Bound;
+
+use blog_api::{snippet_to_html, SearchQuery, SearchTerm, MARK_END, MARK_START};
+use blog_model::BlogPost;
+
+// BM25 tuning: how quickly repeated words stop adding to the score, and how
+// much long posts are penalised.
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+// A word in the title counts as this many words in the body.
+const TITLE_BOOST: f64 = 3.0;
+
+// How many words a snippet shows.
+const SNIPPET_WORDS: usize = 16;
+
+// Words too common to be worth indexing.
+const STOP_WORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "for", "if", "in", "into", "is", "it", "no", "not", "of",
+    "on", "or", "such", "that", "the", "their", "then", "there", "these", "they", "this", "to", "was", "will", "with",
+];
+
+/// An inverted index over post titles and bodies: for every (stemmed) word,
+/// which posts contain it and where. Kept up to date by `MemoryStore` on every
+/// insert, update and delete.
+#[derive(Default)]
+pub struct SearchIndex {
+    // term -> post ID -> positions of the term in the post
+    postings: BTreeMap<String, HashMap<i32, Vec<u32>>>,
+    docs: HashMap<i32, Document>,
+    total_length: u64,
+}
+
+// What the index needs to remember about each post.
+struct Document {
+    // Number of indexed words, for BM25's length normalisation
+    length: u32,
+    // Positions below this are in the title
+    body_start: u32,
+    // Every distinct term, so the post can be removed again
+    terms: Vec<String>,
+}
+
+// A word in some text, with where it came from.
+struct Token {
+    start: usize,
+    end: usize,
+    position: u32,
+    // The stemmed word, or `None` for a stop word
+    term: Option<String>,
+}
+
+// Split text into tokens. Positions count stop words too, so phrases with a
+// stop word in the middle still need their words the right distance apart.
+fn tokenize(text: &str, first_position: u32) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices().chain(std::iter::once((text.len(), ' '))) {
+        match (start, c.is_alphanumeric()) {
+            (None, true) => start = Some(i),
+            (Some(s), false) => {
+                tokens.push(Token {
+                    start: s,
+                    end: i,
+                    position: first_position + tokens.len() as u32,
+                    term: normalize(&text[s..i]),
+                });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    tokens
+}
+
+// Lower-case and stem a word, or `None` if it's a stop word.
+fn normalize(word: &str) -> Option<String> {
+    let word = word.to_lowercase();
+    if STOP_WORDS.contains(&word.as_str()) {
+        None
+    } else {
+        Some(stem(&word))
+    }
+}
+
+// A very small stemmer, in the spirit of Porter's: strip common English
+// suffixes so that "whale", "whales" and "whaling" all index as "whal". It
+// only has to be consistent, since queries are stemmed the same way.
+fn stem(word: &str) -> String {
+    if word.chars().count() <= 3 || !word.is_ascii() {
+        return word.to_string();
+    }
+    let mut stem = if let Some(stem) = word.strip_suffix("sses") {
+        format!("{stem}ss")
+    } else if let Some(stem) = word.strip_suffix("ies") {
+        format!("{stem}y")
+    } else if word.ends_with("ss") {
+        word.to_string()
+    } else if let Some(stem) = word.strip_suffix('s') {
+        stem.to_string()
+    } else {
+        word.to_string()
+    };
+    for suffix in ["ing", "edly", "ed", "ly"] {
+        if let Some(rest) = stem.strip_suffix(suffix) {
+            if rest.len() >= 3 && rest.contains(['a', 'e', 'i', 'o', 'u', 'y']) {
+                stem = rest.to_string();
+                // hopping -> hopp -> hop
+                let bytes = stem.as_bytes();
+                let n = bytes.len();
+                if bytes[n - 1] == bytes[n - 2] && !b"aeioulsz".contains(&bytes[n - 1]) {
+                    stem.pop();
+                }
+                break;
+            }
+        }
+    }
+    if stem.len() >= 4 && stem.ends_with('e') {
+        stem.pop();
+    }
+    stem
+}
+
+impl SearchIndex {
+    /// Add a post to the index. Call `remove` first if it's already there.
+    pub fn add(&mut self, post: &BlogPost) {
+        let title = tokenize(&post.title, 0);
+        // Leave a gap so that a phrase can't run from the title into the body
+        let body_start = title.len() as u32 + 1;
+        let body = tokenize(&post.body, body_start);
+
+        let mut terms = HashSet::new();
+        let mut length = 0;
+        for token in title.into_iter().chain(body) {
+            if let Some(term) = token.term {
+                self.postings.entry(term.clone()).or_default().entry(post.id).or_default().push(token.position);
+                terms.insert(term);
+                length += 1;
+            }
+        }
+
+        self.total_length += u64::from(length);
+        self.docs.insert(
+            post.id,
+            Document {
+                length,
+                body_start,
+                terms: terms.into_iter().collect(),
+            },
+        );
+    }
+
+    /// Take a post out of the index.
+    pub fn remove(&mut self, id: i32) {
+        let Some(doc) = self.docs.remove(&id) else {
+            return;
+        };
+        self.total_length -= u64::from(doc.length);
+        for term in doc.terms {
+            if let Some(posts) = self.postings.get_mut(&term) {
+                posts.remove(&id);
+                if posts.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+    }
+
+    /// The IDs of posts matching every term of the query, with their BM25
+    /// scores, best first.
+    pub fn search(&self, query: &SearchQuery) -> Vec<(i32, f64)> {
+        // Stop words on their own can't match anything, so they're skipped
+        let matches: Vec<HashMap<i32, Vec<u32>>> = query.terms.iter().filter_map(|term| self.find(term)).collect();
+        if matches.is_empty() {
+            return Vec::new();
+        }
+
+        let doc_count = self.docs.len() as f64;
+        let average_length = self.total_length as f64 / doc_count.max(1.0);
+        let mut scores: Vec<(i32, f64)> = matches[0]
+            .keys()
+            .filter(|id| matches[1..].iter().all(|term| term.contains_key(id)))
+            .map(|&id| {
+                let doc = &self.docs[&id];
+                let length_norm = 1.0 - B + B * f64::from(doc.length) / average_length;
+                let score = matches
+                    .iter()
+                    .map(|term| {
+                        let df = term.len() as f64;
+                        let idf = ((doc_count - df + 0.5) / (df + 0.5) + 1.0).ln();
+                        let tf: f64 = term[&id]
+                            .iter()
+                            .map(|&position| if position < doc.body_start { TITLE_BOOST } else { 1.0 })
+                            .sum();
+                        idf * tf * (K1 + 1.0) / (tf + K1 * length_norm)
+                    })
+                    .sum();
+                (id, score)
+            })
+            .collect();
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        scores
+    }
+
+    // Where a single query term appears: post ID -> positions. For a phrase,
+    // the positions are where the phrase starts. `None` if the term is only
+    // stop words.
+    fn find(&self, term: &SearchTerm) -> Option<HashMap<i32, Vec<u32>>> {
+        match term {
+            SearchTerm::Word(word) => {
+                let term = normalize(word)?;
+                Some(self.postings.get(&term).cloned().unwrap_or_default())
+            }
+            SearchTerm::Prefix(prefix) => {
+                // Terms are stemmed, so a prefix matches terms that start
+                // with it ("whal*") or with its own stem ("whales*" finds
+                // "whal"). That's how SQLite's FTS5 matches prefixes too.
+                let stemmed = stem(prefix);
+                let mut terms = HashSet::new();
+                for start in [prefix.as_str(), stemmed.as_str()] {
+                    let range = self.postings.range::<str, _>((Bound::Included(start), Bound::Unbounded));
+                    terms.extend(range.take_while(|(term, _)| term.starts_with(start)).map(|(term, _)| term));
+                }
+
+                let mut found: HashMap<i32, Vec<u32>> = HashMap::new();
+                for term in terms {
+                    for (id, positions) in &self.postings[term] {
+                        found.entry(*id).or_default().extend(positions);
+                    }
+                }
+                Some(found)
+            }
+            SearchTerm::Phrase(words) => {
+                // Each non-stop word, with its distance from the start of the phrase
+                let parts: Vec<(u32, String)> = words
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(offset, word)| Some((offset as u32, normalize(word)?)))
+                    .collect();
+                let (first_offset, first_term) = parts.first()?;
+                let empty = HashMap::new();
+                let postings: Vec<&HashMap<i32, Vec<u32>>> =
+                    parts.iter().map(|(_, term)| self.postings.get(term).unwrap_or(&empty)).collect();
+
+                let mut found = HashMap::new();
+                for (id, positions) in self.postings.get(first_term).unwrap_or(&empty) {
+                    let starts: Vec<u32> = positions
+                        .iter()
+                        .filter_map(|&position| position.checked_sub(*first_offset))
+                        .filter(|&start| {
+                            parts.iter().zip(&postings).all(|((offset, _), posts)| {
+                                posts.get(id).is_some_and(|positions| positions.contains(&(start + offset)))
+                            })
+                        })
+                        .collect();
+                    if !starts.is_empty() {
+                        found.insert(*id, starts);
+                    }
+                }
+                Some(found)
+            }
+        }
+    }
+}
+
+/// An HTML snippet of a post with the words matching the query highlighted.
+/// It comes from the body if that matches, otherwise from the title.
+pub fn snippet(post: &BlogPost, query: &SearchQuery) -> String {
+    let mut words = HashSet::new();
+    let mut prefixes = Vec::new();
+    for term in &query.terms {
+        match term {
+            SearchTerm::Word(word) => words.extend(normalize(word)),
+            SearchTerm::Prefix(prefix) => prefixes.push((prefix.as_str(), stem(prefix))),
+            SearchTerm::Phrase(phrase) => words.extend(phrase.iter().filter_map(|word| normalize(word))),
+        }
+    }
+    let is_match = |token: &Token| {
+        token.term.as_ref().is_some_and(|term| {
+            words.contains(term)
+                || prefixes.iter().any(|(prefix, stemmed)| term.starts_with(prefix) || term.starts_with(stemmed.as_str()))
+        })
+    };
+
+    let body = tokenize(&post.body, 0);
+    let (text, tokens) = if body.iter().any(is_match) {
+        (post.body.as_str(), body)
+    } else {
+        (post.title.as_str(), tokenize(&post.title, 0))
+    };
+    if tokens.is_empty() {
+        return snippet_to_html(text);
+    }
+
+    // A window of words, starting a little before the first match
+    let first = tokens.iter().position(is_match).unwrap_or(0);
+    let start = first.saturating_sub(3);
+    let end = (start + SNIPPET_WORDS).min(tokens.len());
+
+    let mut raw = String::new();
+    if start > 0 {
+        raw.push('…');
+    }
+    let mut cursor = tokens[start].start;
+    for token in &tokens[start..end] {
+        raw.push_str(&text[cursor..token.start]);
+        if is_match(token) {
+            raw.push(MARK_START);
+            raw.push_str(&text[token.start..token.end]);
+            raw.push(MARK_END);
+        } else {
+            raw.push_str(&text[token.start..token.end]);
+        }
+        cursor = token.end;
+    }
+    if end < tokens.len() {
+        raw.push('…');
+    } else {
+        raw.push_str(&text[cursor..]);
+    }
+    snippet_to_html(&raw)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn post(id: i32, title: &str, body: &str) -> BlogPost {
+        BlogPost {
+            id,
+            date: chrono::Utc::now(),
+            title: title.to_string(),
+            body: body.to_string(),
+            author: "herbert".to_string(),
+            tags: vec![],
+            version: 1,
+        }
+    }
+
+    fn index() -> SearchIndex {
+        let mut index = SearchIndex::default();
+        index.add(&post(1, "Moby Dick", "Call me Ishmael. Some years ago I went whaling."));
+        index.add(&post(2, "Whales", "The whale is the largest animal in the sea."));
+        index.add(&post(3, "Boats", "Ishmael, call me later about the boats."));
+        index.add(&post(4, "Hopping", "Rabbits were hopping and running quickly."));
+        index
+    }
+
+    fn ids(index: &SearchIndex, query: &str) -> Vec<i32> {
+        let mut ids: Vec<i32> = index.search(&SearchQuery::parse(query).unwrap()).into_iter().map(|(id, _)| id).collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn stems_common_suffixes() {
+        for (word, stemmed) in [
+            ("whale", "whal"),
+            ("whales", "whal"),
+            ("whaling", "whal"),
+            ("ponies", "pony"),
+            ("classes", "class"),
+            ("glass", "glass"),
+            ("hopping", "hop"),
+            ("running", "run"),
+            ("falling", "fall"),
+            ("quickly", "quick"),
+            ("wanted", "want"),
+            ("cat", "cat"),
+            ("café", "café"),
+        ] {
+            assert_eq!(stem(word), stemmed, "{word}");
+        }
+    }
+
+    #[test]
+    fn words_match_any_form() {
+        assert_eq!(ids(&index(), "whale"), [1, 2]);
+        assert_eq!(ids(&index(), "Whaling"), [1, 2]);
+        assert_eq!(ids(&index(), "hop"), [4]);
+        assert_eq!(ids(&index(), "squid"), [] as [i32; 0]);
+    }
+
+    #[test]
+    fn every_term_must_match() {
+        assert_eq!(ids(&index(), "ishmael call"), [1, 3]);
+        assert_eq!(ids(&index(), "ishmael whale"), [1]);
+    }
+
+    #[test]
+    fn phrases_need_their_words_in_order() {
+        assert_eq!(ids(&index(), r#""call me ishmael""#), [1]);
+        assert_eq!(ids(&index(), r#""ishmael call me""#), [3]);
+        assert_eq!(ids(&index(), r#""me call""#), [] as [i32; 0]);
+        // A phrase can't run from the title into the body
+        assert_eq!(ids(&index(), r#""dick call""#), [] as [i32; 0]);
+    }
+
+    #[test]
+    fn phrases_count_stop_words_as_gaps() {
+        assert_eq!(ids(&index(), r#""largest animal in the sea""#), [2]);
+        assert_eq!(ids(&index(), r#""largest animal in a sea""#), [2]);
+        assert_eq!(ids(&index(), r#""largest animal in sea""#), [] as [i32; 0]);
+        assert_eq!(ids(&index(), r#""animal sea""#), [] as [i32; 0]);
+    }
+
+    #[test]
+    fn prefixes_match_stems_and_stemmed_prefixes() {
+        assert_eq!(ids(&index(), "whal*"), [1, 2]);
+        assert_eq!(ids(&index(), "whale*"), [1, 2]);
+        assert_eq!(ids(&index(), "whales*"), [1, 2]);
+        assert_eq!(ids(&index(), "rabbit*"), [4]);
+        assert_eq!(ids(&index(), "rabbits*"), [4]);
+        // "hopping" is stored as "hop", which "hopp" isn't a prefix of
+        assert_eq!(ids(&index(), "hopp*"), [] as [i32; 0]);
+        assert_eq!(ids(&index(), "ish*"), [1, 3]);
+        assert_eq!(ids(&index(), "whalez*"), [] as [i32; 0]);
+    }
+
+    #[test]
+    fn stop_words_are_ignored() {
+        assert_eq!(ids(&index(), "the"), [] as [i32; 0]);
+        assert_eq!(ids(&index(), r#""the and""#), [] as [i32; 0]);
+        assert_eq!(ids(&index(), "the whale"), [1, 2]);
+    }
+
+    #[test]
+    fn titles_score_higher_than_bodies() {
+        let index = index();
+        let results = index.search(&SearchQuery::parse("whale").unwrap());
+        assert_eq!(results[0].0, 2);
+        assert!(results[0].1 > results[1].1);
+        assert!(results.iter().all(|(_, score)| *score > 0.0));
+    }
+
+    #[test]
+    fn removed_posts_are_forgotten() {
+        let mut index = index();
+        index.remove(2);
+        assert_eq!(ids(&index, "whale"), [1]);
+        assert_eq!(ids(&index, "whales*"), [1]);
+        index.remove(1);
+        assert!(index.postings.keys().all(|term| term != "whal"));
+    }
+
+    #[test]
+    fn snippets_mark_prefix_matches() {
+        let post = post(2, "Whales", "The whale is the largest animal in the sea.");
+        let snippet = snippet(&post, &SearchQuery::parse("whale*").unwrap());
+        assert_eq!(snippet, "The <mark>whale</mark> is the largest animal in the sea.");
+    }
+}
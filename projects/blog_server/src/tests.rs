@@ -0,0 +1,492 @@
+// Requests through the whole router, backed by a `MemoryStore`
+
+use std::sync::{Arc, OnceLock};
+
+use axum::body::Body;
+use axum::http::{header, HeaderMap, Request, StatusCode};
+use axum::Router;
+use blog_api::{PostStore, Users};
+use blog_model::NewPost;
+use server_config::Config;
+use tower::ServiceExt;
+
+use crate::memory_store::MemoryStore;
+
+// Who can log in, and as what. Everyone's password is `password(username)`.
+const USERS: [(&str, &str); 5] = [
+    ("herbert", "author"),
+    ("ashley", "author"),
+    ("edith", "editor"),
+    ("rita", "reader"),
+    ("admin", "admin"),
+];
+
+fn password(username: &str) -> String {
+    format!("{username}'s password")
+}
+
+// The users file for `USERS`. Hashing is slow, so it's only done once.
+fn users_json() -> &'static str {
+    static JSON: OnceLock<String> = OnceLock::new();
+    JSON.get_or_init(|| {
+        let hasher = Users::default();
+        let users: Vec<_> = USERS
+            .iter()
+            .map(|(username, role)| {
+                let password = hasher.hash_password(&password(username));
+                serde_json::json!({ "username": username, "password": password, "role": role })
+            })
+            .collect();
+        serde_json::to_string(&users).unwrap()
+    })
+}
+
+fn config() -> Config {
+    Config {
+        read_rate: 0.0,
+        write_rate: 0.0,
+        token_secret: Some("a test secret, long enough to sign with".to_string()),
+        ..Config::default()
+    }
+}
+
+async fn app() -> Router {
+    app_with(config()).await
+}
+
+// The router with three posts in it, and `USERS`
+async fn app_with(mut config: Config) -> Router {
+    let store = Arc::new(MemoryStore::new());
+    let posts = [
+        ("Whales", "herbert", vec!["sea"]),
+        ("Ships", "herbert", vec!["sea", "boats"]),
+        ("Gophers", "ashley", vec!["go"]),
+    ];
+    for (title, author, tags) in posts {
+        let post = NewPost {
+            title: title.to_string(),
+            body: format!("All about {title}"),
+            author: author.to_string(),
+            tags: tags.into_iter().map(String::from).collect(),
+        };
+        store.create(post).await.unwrap();
+    }
+
+    // The users are read straight away, so the file needn't outlive this
+    let users = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(users.path(), users_json()).unwrap();
+    config.users_file = Some(users.path().to_path_buf());
+    blog_api::router_from_config(store, &config).unwrap()
+}
+
+// A response, with its body read
+struct Reply {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: String,
+}
+
+impl Reply {
+    fn json(&self) -> serde_json::Value {
+        serde_json::from_str(&self.body).unwrap_or_else(|e| panic!("{e}: {}", self.body))
+    }
+
+    fn header(&self, name: impl header::AsHeaderName) -> Option<&str> {
+        self.headers.get(name).map(|value| value.to_str().unwrap())
+    }
+}
+
+async fn send(app: &Router, request: Request<Body>) -> Reply {
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    Reply {
+        status,
+        headers,
+        body: String::from_utf8(body.to_vec()).unwrap(),
+    }
+}
+
+// A request with a JSON body, sent with `token` if there is one
+fn json_request(method: &str, uri: &str, token: Option<&str>, body: serde_json::Value) -> Request<Body> {
+    let mut request = Request::builder().method(method).uri(uri).header(header::CONTENT_TYPE, "application/json");
+    if let Some(token) = token {
+        request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+    }
+    request.body(Body::from(body.to_string())).unwrap()
+}
+
+// A request without a body, sent with `token` if there is one
+fn request(method: &str, uri: &str, token: Option<&str>) -> Request<Body> {
+    let mut request = Request::builder().method(method).uri(uri);
+    if let Some(token) = token {
+        request = request.header(header::AUTHORIZATION, format!("Bearer {token}"));
+    }
+    request.body(Body::empty()).unwrap()
+}
+
+async fn log_in(app: &Router, username: &str, password: &str) -> Reply {
+    let login = serde_json::json!({ "username": username, "password": password });
+    send(app, json_request("POST", "/auth/login", None, login)).await
+}
+
+// A bearer token for one of `USERS`
+async fn token(app: &Router, username: &str) -> String {
+    let reply = log_in(app, username, &password(username)).await;
+    assert_eq!(reply.status, StatusCode::OK, "{}", reply.body);
+    reply.json()["token"].as_str().unwrap().to_string()
+}
+
+async fn get(app: Router, uri: &str) -> String {
+    let response = app.oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK, "{uri}");
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+// The titles of a feed's items or entries
+fn feed_titles(xml: &str) -> Vec<String> {
+    let doc = roxmltree::Document::parse(xml).unwrap();
+    let mut titles: Vec<String> = doc
+        .descendants()
+        .filter(|node| node.has_tag_name("item") || node.has_tag_name("entry"))
+        .filter_map(|item| item.children().find(|child| child.has_tag_name("title")))
+        .map(|title| title.text().unwrap_or_default().to_string())
+        .collect();
+    titles.sort();
+    titles
+}
+
+#[tokio::test]
+async fn feeds_hold_every_post() {
+    for feed in ["/feed.rss", "/feed.atom"] {
+        assert_eq!(feed_titles(&get(app().await, feed).await), ["Gophers", "Ships", "Whales"]);
+    }
+}
+
+#[tokio::test]
+async fn author_feeds_hold_only_their_posts() {
+    for feed in ["/feed.rss?author=herbert", "/feed.atom?author=herbert"] {
+        assert_eq!(feed_titles(&get(app().await, feed).await), ["Ships", "Whales"]);
+    }
+    assert!(feed_titles(&get(app().await, "/feed.rss?author=nobody").await).is_empty());
+}
+
+#[tokio::test]
+async fn tag_feeds_hold_only_tagged_posts() {
+    for feed in ["/feed.rss?tag=boats", "/feed.atom?tag=boats"] {
+        assert_eq!(feed_titles(&get(app().await, feed).await), ["Ships"]);
+    }
+    assert_eq!(feed_titles(&get(app().await, "/feed.atom?tag=sea&author=herbert").await), ["Ships", "Whales"]);
+    assert!(feed_titles(&get(app().await, "/feed.atom?tag=go&author=herbert").await).is_empty());
+}
+
+#[tokio::test]
+async fn too_many_failed_logins_lock_out_until_unlocked() {
+    let app = app_with(Config {
+        login_max_failures: 2,
+        login_lockout: 60,
+        ..config()
+    })
+    .await;
+    // Every request here comes from the same (unknown) address, so the admin
+    // logs in before it's locked out
+    let admin = token(&app, "admin").await;
+
+    assert_eq!(log_in(&app, "herbert", "wrong").await.status, StatusCode::UNAUTHORIZED);
+    let locked = log_in(&app, "herbert", "wrong").await;
+    assert_eq!(locked.status, StatusCode::LOCKED);
+    assert_eq!(locked.json()["code"], "locked_out");
+    assert!(locked.json()["message"].as_str().unwrap().contains("try again after"), "{}", locked.body);
+    let retry_after: u64 = locked.header(header::RETRY_AFTER).unwrap().parse().unwrap();
+    assert!((59..=60).contains(&retry_after), "{retry_after}");
+
+    // Even the right password doesn't work now
+    assert_eq!(log_in(&app, "herbert", &password("herbert")).await.status, StatusCode::LOCKED);
+
+    // Only admins can unlock, and usernames and addresses are unlocked apart
+    let author = send(&app, request("DELETE", "/auth/lockouts/users/herbert", Some(&admin))).await;
+    assert_eq!(author.status, StatusCode::NO_CONTENT);
+    assert_eq!(log_in(&app, "herbert", &password("herbert")).await.status, StatusCode::LOCKED);
+    let as_username = send(&app, request("DELETE", "/auth/lockouts/users/0.0.0.0", Some(&admin))).await;
+    assert_eq!(as_username.status, StatusCode::NOT_FOUND);
+    let address = send(&app, request("DELETE", "/auth/lockouts/ips/0.0.0.0", Some(&admin))).await;
+    assert_eq!(address.status, StatusCode::NO_CONTENT);
+
+    token(&app, "herbert").await;
+}
+
+#[tokio::test]
+async fn only_admins_can_unlock() {
+    let app = app().await;
+    let editor = token(&app, "edith").await;
+    let reply = send(&app, request("DELETE", "/auth/lockouts/users/herbert", Some(&editor))).await;
+    assert_eq!(reply.status, StatusCode::FORBIDDEN);
+    let reply = send(&app, request("DELETE", "/auth/lockouts/ips/not-an-address", Some(&editor))).await;
+    assert_eq!(reply.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn changes_need_a_precondition() {
+    let app = app().await;
+    let herbert = token(&app, "herbert").await;
+    let patch = json_request("PATCH", "/blog/1", Some(&herbert), serde_json::json!({ "title": "Orcas" }));
+    let replace = serde_json::json!({ "title": "Orcas", "body": "All about orcas" });
+    let replace = json_request("PUT", "/blog/1", Some(&herbert), replace);
+    for request in [patch, replace, request("DELETE", "/blog/1", Some(&herbert))] {
+        let reply = send(&app, request).await;
+        assert_eq!(reply.status, StatusCode::PRECONDITION_REQUIRED, "{}", reply.body);
+        assert_eq!(reply.json()["code"], "precondition_required");
+    }
+    assert_eq!(send(&app, request("GET", "/blog/1", None)).await.json()["title"], "Whales");
+}
+
+#[tokio::test]
+async fn stale_changes_are_refused() {
+    let app = app().await;
+    let herbert = token(&app, "herbert").await;
+    let etag = send(&app, request("GET", "/blog/1", None)).await.header(header::ETAG).unwrap().to_string();
+
+    let patch = |version: i64| serde_json::json!({ "title": "Big whales", "version": version });
+    assert_eq!(send(&app, json_request("PATCH", "/blog/1", Some(&herbert), patch(1))).await.status, StatusCode::OK);
+    let stale = send(&app, json_request("PATCH", "/blog/1", Some(&herbert), patch(1))).await;
+    assert_eq!(stale.status, StatusCode::PRECONDITION_FAILED);
+    assert_eq!(stale.json()["code"], "precondition_failed");
+
+    let request_with_etag = |etag: &str| {
+        let mut request = json_request("PATCH", "/blog/1", Some(&herbert), serde_json::json!({ "title": "Orcas" }));
+        request.headers_mut().insert(header::IF_MATCH, etag.parse().unwrap());
+        request
+    };
+    assert_eq!(send(&app, request_with_etag(&etag)).await.status, StatusCode::PRECONDITION_FAILED);
+    let current = send(&app, request("GET", "/blog/1", None)).await;
+    assert_eq!(current.json()["title"], "Big whales");
+    let etag = current.header(header::ETAG).unwrap();
+    assert_eq!(send(&app, request_with_etag(etag)).await.status, StatusCode::OK);
+
+    let stale = send(&app, request("DELETE", "/blog/1?version=2", Some(&herbert))).await;
+    assert_eq!(stale.status, StatusCode::PRECONDITION_FAILED);
+    let deleted = send(&app, request("DELETE", "/blog/1?version=3", Some(&herbert))).await;
+    assert_eq!(deleted.status, StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn patches_leave_the_author_and_date_alone() {
+    let app = app().await;
+    let herbert = token(&app, "herbert").await;
+    let before = send(&app, request("GET", "/blog/1", None)).await.json();
+    let patch = serde_json::json!({
+        "title": "Orcas",
+        "author": "ashley",
+        "date": "2000-01-01T00:00:00Z",
+        "version": 1,
+    });
+    let after = send(&app, json_request("PATCH", "/blog/1", Some(&herbert), patch)).await.json();
+    assert_eq!(after["title"], "Orcas");
+    assert_eq!((&after["author"], &after["date"]), (&before["author"], &before["date"]));
+}
+
+#[tokio::test]
+async fn errors_are_json_with_the_request_id() {
+    let app = app().await;
+    let mut missing = request("GET", "/blog/999", None);
+    missing.headers_mut().insert("x-request-id", "from-the-proxy".parse().unwrap());
+    let reply = send(&app, missing).await;
+    assert_eq!(reply.status, StatusCode::NOT_FOUND);
+    assert_eq!(reply.header(header::CONTENT_TYPE), Some("application/json"));
+    let body = reply.json();
+    assert_eq!((&body["code"], &body["request_id"]), (&"not_found".into(), &"from-the-proxy".into()));
+    assert!(!body["message"].as_str().unwrap().is_empty());
+
+    // Without one from the client, the server makes one up
+    let reply = send(&app, request("GET", "/blog/not-a-number", None)).await;
+    assert_eq!(reply.status, StatusCode::BAD_REQUEST);
+    assert_eq!(reply.json()["code"], "bad_request");
+    assert_eq!(reply.json()["request_id"].as_str(), reply.header("x-request-id"));
+}
+
+#[tokio::test]
+async fn bad_json_is_unprocessable() {
+    let app = app().await;
+    let herbert = token(&app, "herbert").await;
+    let wrong_type = json_request("POST", "/blog/new", Some(&herbert), serde_json::json!({ "title": 5, "body": "" }));
+    let mut not_json = json_request("POST", "/blog/new", Some(&herbert), serde_json::json!(null));
+    *not_json.body_mut() = Body::from("{ \"title\": ");
+    for request in [wrong_type, not_json] {
+        let reply = send(&app, request).await;
+        assert_eq!(reply.status, StatusCode::UNPROCESSABLE_ENTITY, "{}", reply.body);
+        assert_eq!(reply.json()["code"], "invalid_json");
+        assert!(reply.json()["request_id"].as_str().is_some_and(|id| !id.is_empty()));
+    }
+}
+
+// A GET sending a conditional header
+fn conditional_get(uri: &str, name: header::HeaderName, value: &str) -> Request<Body> {
+    let mut request = request("GET", uri, None);
+    request.headers_mut().insert(name, value.parse().unwrap());
+    request
+}
+
+#[tokio::test]
+async fn unchanged_responses_are_not_modified() {
+    let app = app().await;
+    let herbert = token(&app, "herbert").await;
+    let uris = ["/blog/1", "/blog/1/html", "/blog/all?tag=sea", "/posts/1", "/", "/feed.rss", "/feed.atom"];
+    let mut etags = vec![];
+    for uri in uris {
+        let reply = send(&app, request("GET", uri, None)).await;
+        assert_eq!(reply.status, StatusCode::OK, "{uri}");
+        let etag = reply.header(header::ETAG).unwrap().to_string();
+        let modified = reply.header(header::LAST_MODIFIED).unwrap();
+
+        let cached = send(&app, conditional_get(uri, header::IF_NONE_MATCH, &etag)).await;
+        assert_eq!((cached.status, cached.body.as_str()), (StatusCode::NOT_MODIFIED, ""), "{uri}");
+        assert_eq!(cached.header(header::ETAG), Some(etag.as_str()), "{uri}");
+        let weak = send(&app, conditional_get(uri, header::IF_NONE_MATCH, &format!("W/{etag}"))).await;
+        assert_eq!(weak.status, StatusCode::NOT_MODIFIED, "{uri}");
+        let cached = send(&app, conditional_get(uri, header::IF_MODIFIED_SINCE, modified)).await;
+        assert_eq!(cached.status, StatusCode::NOT_MODIFIED, "{uri}");
+
+        let other = send(&app, conditional_get(uri, header::IF_NONE_MATCH, "\"something else\"")).await;
+        assert_eq!(other.status, StatusCode::OK, "{uri}");
+        let long_ago = conditional_get(uri, header::IF_MODIFIED_SINCE, "Sat, 01 Jan 2000 00:00:00 GMT");
+        let earlier = send(&app, long_ago).await;
+        assert_eq!(earlier.status, StatusCode::OK, "{uri}");
+        etags.push(etag);
+    }
+
+    // Once the post changes, none of the old copies are current
+    let patch = serde_json::json!({ "title": "Big whales", "body": "Bigger than ever", "version": 1 });
+    assert_eq!(send(&app, json_request("PATCH", "/blog/1", Some(&herbert), patch)).await.status, StatusCode::OK);
+    for (uri, etag) in uris.iter().zip(&etags) {
+        let reply = send(&app, conditional_get(uri, header::IF_NONE_MATCH, etag)).await;
+        assert_eq!(reply.status, StatusCode::OK, "{uri}");
+        assert_ne!(reply.header(header::ETAG), Some(etag.as_str()), "{uri}");
+    }
+}
+
+#[tokio::test]
+async fn clients_are_limited_until_their_bucket_refills() {
+    // Two requests at once, then one every 50ms
+    let app = app_with(Config {
+        read_rate: 20.0,
+        read_burst: 2,
+        write_rate: 20.0,
+        write_burst: 2,
+        ..config()
+    })
+    .await;
+    let refill = || tokio::time::sleep(std::time::Duration::from_millis(100));
+
+    for _ in 0..2 {
+        assert_eq!(send(&app, request("GET", "/blog/1", None)).await.status, StatusCode::OK);
+    }
+    let limited = send(&app, request("GET", "/blog/1", None)).await;
+    assert_eq!(limited.status, StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(limited.json()["code"], "too_many_requests");
+    assert_eq!(limited.header(header::RETRY_AFTER), Some("1"));
+    refill().await;
+    assert_eq!(send(&app, request("GET", "/blog/1", None)).await.status, StatusCode::OK);
+
+    // Logged-in users each have their own budget, apart from their address's
+    let herbert = token(&app, "herbert").await;
+    let ashley = token(&app, "ashley").await;
+    let new_post = |token: &str| {
+        let post = serde_json::json!({ "title": "Squid", "body": "All about squid" });
+        json_request("POST", "/blog/new", Some(token), post)
+    };
+    for _ in 0..2 {
+        assert!(send(&app, new_post(&herbert)).await.status.is_success());
+    }
+    let limited = send(&app, new_post(&herbert)).await;
+    assert_eq!(limited.status, StatusCode::TOO_MANY_REQUESTS);
+    assert!(limited.header(header::RETRY_AFTER).is_some());
+    assert!(send(&app, new_post(&ashley)).await.status.is_success());
+    refill().await;
+    assert!(send(&app, new_post(&herbert)).await.status.is_success());
+}
+
+#[tokio::test]
+async fn only_owners_and_trusted_roles_change_posts() {
+    let app = app().await;
+    let herbert = token(&app, "herbert").await;
+    let edith = token(&app, "edith").await;
+    let rita = token(&app, "rita").await;
+    let admin = token(&app, "admin").await;
+    let patch = |version: i64| serde_json::json!({ "title": "Moles", "version": version });
+    let replace = serde_json::json!({ "title": "Moles", "body": "All about moles", "version": 1 });
+
+    // Gophers is ashley's
+    for refused in [
+        json_request("PATCH", "/blog/3", Some(&herbert), patch(1)),
+        json_request("PUT", "/blog/3", Some(&herbert), replace),
+        request("DELETE", "/blog/3?version=1", Some(&herbert)),
+        json_request("PATCH", "/blog/3", Some(&rita), patch(1)),
+        json_request("POST", "/blog/new", Some(&rita), serde_json::json!({ "title": "Moles", "body": "" })),
+    ] {
+        let reply = send(&app, refused).await;
+        assert_eq!(reply.status, StatusCode::FORBIDDEN, "{}", reply.body);
+        assert_eq!(reply.json()["code"], "forbidden");
+    }
+    assert_eq!(send(&app, request("GET", "/blog/3", None)).await.json()["title"], "Gophers");
+
+    // Editors can edit anyone's posts, but only delete their own
+    assert_eq!(send(&app, json_request("PATCH", "/blog/3", Some(&edith), patch(1))).await.status, StatusCode::OK);
+    let reply = send(&app, request("DELETE", "/blog/3?version=2", Some(&edith))).await;
+    assert_eq!(reply.status, StatusCode::FORBIDDEN);
+    let reply = send(&app, request("DELETE", "/blog/3?version=2", Some(&admin))).await;
+    assert_eq!(reply.status, StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn only_owners_and_trusted_roles_delete_comments() {
+    let app = app().await;
+    let herbert = token(&app, "herbert").await;
+    let ashley = token(&app, "ashley").await;
+    let edith = token(&app, "edith").await;
+    let rita = token(&app, "rita").await;
+    let mut ids = vec![];
+    for body in ["Big", "Wet"] {
+        let comment = json_request("POST", "/blog/1/comments", Some(&rita), serde_json::json!({ "body": body }));
+        let reply = send(&app, comment).await;
+        assert_eq!(reply.status, StatusCode::CREATED, "{}", reply.body);
+        ids.push(reply.json()["id"].as_i64().unwrap());
+    }
+
+    // Not even the post's author can delete a reader's comment
+    for token in [&herbert, &ashley] {
+        let reply = send(&app, request("DELETE", &format!("/blog/1/comments/{}", ids[0]), Some(token))).await;
+        assert_eq!(reply.status, StatusCode::FORBIDDEN);
+        assert_eq!(reply.json()["code"], "forbidden");
+    }
+    let reply = send(&app, request("DELETE", &format!("/blog/1/comments/{}", ids[0]), Some(&rita))).await;
+    assert_eq!(reply.status, StatusCode::NO_CONTENT);
+    let reply = send(&app, request("DELETE", &format!("/blog/1/comments/{}", ids[1]), Some(&edith))).await;
+    assert_eq!(reply.status, StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn feeds_for_other_hosts_are_cached_apart() {
+    let app = app().await;
+    for feed in ["/feed.rss", "/feed.atom"] {
+        let at = |host: &str| {
+            let mut request = request("GET", feed, None);
+            request.headers_mut().insert(header::HOST, host.parse().unwrap());
+            request
+        };
+        let first = send(&app, at("blog.example")).await;
+        let etag = first.header(header::ETAG).unwrap();
+        assert!(first.body.contains("http://blog.example/posts/1"), "{}", first.body);
+
+        let mut cached = at("blog.example");
+        cached.headers_mut().insert(header::IF_NONE_MATCH, etag.parse().unwrap());
+        assert_eq!(send(&app, cached).await.status, StatusCode::NOT_MODIFIED);
+        let mut elsewhere = at("mirror.example");
+        elsewhere.headers_mut().insert(header::IF_NONE_MATCH, etag.parse().unwrap());
+        let elsewhere = send(&app, elsewhere).await;
+        assert_eq!(elsewhere.status, StatusCode::OK);
+        assert_ne!(elsewhere.header(header::ETAG), Some(etag));
+        assert!(elsewhere.body.contains("http://mirror.example/posts/1"), "{}", elsewhere.body);
+    }
+}
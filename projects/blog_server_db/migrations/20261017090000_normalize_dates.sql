@@ -0,0 +1,5 @@
+-- Store every date as a fixed-width UTC timestamp, e.g. 2021-01-01T00:00:00.000Z,
+-- so that it parses as a DateTime<Utc> and sorts correctly as text.
+UPDATE blog_posts
+SET date = strftime('%Y-%m-%dT%H:%M:%fZ', date)
+WHERE strftime('%Y-%m-%dT%H:%M:%fZ', date) IS NOT NULL;
@@ -0,0 +1,25 @@
+-- Full-text index of post titles and bodies. It's an external content table:
+-- the text lives in blog_posts, and the triggers below keep the index in step.
+CREATE VIRTUAL TABLE blog_posts_fts USING fts5(
+    title,
+    body,
+    content = 'blog_posts',
+    content_rowid = 'id',
+    tokenize = 'porter unicode61'
+);
+
+-- Index the posts we already have
+INSERT INTO blog_posts_fts (blog_posts_fts) VALUES ('rebuild');
+
+CREATE TRIGGER blog_posts_fts_insert AFTER INSERT ON blog_posts BEGIN
+    INSERT INTO blog_posts_fts (rowid, title, body) VALUES (new.id, new.title, new.body);
+END;
+
+CREATE TRIGGER blog_posts_fts_delete AFTER DELETE ON blog_posts BEGIN
+    INSERT INTO blog_posts_fts (blog_posts_fts, rowid, title, body) VALUES ('delete', old.id, old.title, old.body);
+END;
+
+CREATE TRIGGER blog_posts_fts_update AFTER UPDATE OF title, body ON blog_posts BEGIN
+    INSERT INTO blog_posts_fts (blog_posts_fts, rowid, title, body) VALUES ('delete', old.id, old.title, old.body);
+    INSERT INTO blog_posts_fts (rowid, title, body) VALUES (new.id, new.title, new.body);
+END;
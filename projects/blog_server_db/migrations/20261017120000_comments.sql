@@ -0,0 +1,15 @@
+-- Readers' comments. Replies point at their parent comment. A comment with
+-- replies is never deleted outright, only tombstoned (deleted = 1), so the
+-- parent_id cascade only matters when a whole post goes.
+CREATE TABLE comments (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    post_id INTEGER NOT NULL REFERENCES blog_posts (id) ON DELETE CASCADE,
+    parent_id INTEGER REFERENCES comments (id) ON DELETE CASCADE,
+    date TEXT NOT NULL,
+    author TEXT NOT NULL,
+    body TEXT NOT NULL,
+    deleted INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX comments_post_id ON comments (post_id);
+CREATE INDEX comments_parent_id ON comments (parent_id);
@@ -0,0 +1,46 @@
+-- A version stamp for the whole store, so clients can cache lists of posts.
+-- The triggers below bump it on any change to posts, their tags or their
+-- comments.
+CREATE TABLE store_meta (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    version INTEGER NOT NULL,
+    modified TEXT NOT NULL
+);
+
+INSERT INTO store_meta (id, version, modified) VALUES (1, 0, strftime('%Y-%m-%dT%H:%M:%fZ', 'now'));
+
+CREATE TRIGGER blog_posts_version_insert AFTER INSERT ON blog_posts BEGIN
+    UPDATE store_meta SET version = version + 1, modified = strftime('%Y-%m-%dT%H:%M:%fZ', 'now');
+END;
+
+CREATE TRIGGER blog_posts_version_update AFTER UPDATE ON blog_posts BEGIN
+    UPDATE store_meta SET version = version + 1, modified = strftime('%Y-%m-%dT%H:%M:%fZ', 'now');
+END;
+
+CREATE TRIGGER blog_posts_version_delete AFTER DELETE ON blog_posts BEGIN
+    UPDATE store_meta SET version = version + 1, modified = strftime('%Y-%m-%dT%H:%M:%fZ', 'now');
+END;
+
+CREATE TRIGGER post_tags_version_insert AFTER INSERT ON post_tags BEGIN
+    UPDATE store_meta SET version = version + 1, modified = strftime('%Y-%m-%dT%H:%M:%fZ', 'now');
+END;
+
+CREATE TRIGGER post_tags_version_update AFTER UPDATE ON post_tags BEGIN
+    UPDATE store_meta SET version = version + 1, modified = strftime('%Y-%m-%dT%H:%M:%fZ', 'now');
+END;
+
+CREATE TRIGGER post_tags_version_delete AFTER DELETE ON post_tags BEGIN
+    UPDATE store_meta SET version = version + 1, modified = strftime('%Y-%m-%dT%H:%M:%fZ', 'now');
+END;
+
+CREATE TRIGGER comments_version_insert AFTER INSERT ON comments BEGIN
+    UPDATE store_meta SET version = version + 1, modified = strftime('%Y-%m-%dT%H:%M:%fZ', 'now');
+END;
+
+CREATE TRIGGER comments_version_update AFTER UPDATE ON comments BEGIN
+    UPDATE store_meta SET version = version + 1, modified = strftime('%Y-%m-%dT%H:%M:%fZ', 'now');
+END;
+
+CREATE TRIGGER comments_version_delete AFTER DELETE ON comments BEGIN
+    UPDATE store_meta SET version = version + 1, modified = strftime('%Y-%m-%dT%H:%M:%fZ', 'now');
+END;
@@ -0,0 +1,3 @@
+-- Every post carries a version, bumped on each update, so that clients can
+-- make sure they're changing the post they think they are.
+ALTER TABLE blog_posts ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
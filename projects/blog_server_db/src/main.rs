@@ -1,95 +1,43 @@
-use axum::{routing::get, Router};
-use std::net::SocketAddr;
-use serde::{Serialize, Deserialize};
-use axum::Extension;
+use std::sync::Arc;
+use std::time::Duration;
+use blog_api::PostStore;
+use server_config::Config;
 
-use sqlx::FromRow;
-#[derive(Serialize, Deserialize, Clone, FromRow)]
-struct BlogPost {
-    id: i32,
-    date: String,
-    title: String,
-    body: String,
-    author: String,
-}
+mod sqlite_store;
+use sqlite_store::SqliteStore;
 
 #[tokio::main]
 async fn main() {
-    // Set the database URL
-    dotenv::dotenv().ok(); // Load the .env file
-    let database_url = std::env::var("DATABASE_URL").expect("You've not set the DATABASE_URL");
-
-    // Obtain a connection pool
-    let connection_pool = sqlx::SqlitePool::connect(&database_url)
-        .await
-        .expect("Unable to connect to the database");
-
-    // Run migrations if they haven't been applied
-    sqlx::migrate!()
-        .run(&connection_pool)
-        .await
-        .expect("Unable to run database migrations");
-
-    // Bind the default route to the function `say_hello_text`
-    use axum::routing::post;
-    use axum::Extension;
-    let app = Router::new()
-        .route("/", get(say_hello_text))
-        .route("/blog/all", get(all_posts))
-        .route("/blog/:id", get(get_post))
-        .route("/blog/new", post(new_post))
-        .layer(Extension(connection_pool));
-
-    // Listen on localhost, port 3000
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3001));
-
-    // Start the server
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
-}
-
-// Return a static string
-async fn say_hello_text() -> &'static str {
-    "Hello, world!"
-}
-
-// Return all blog posts
-use axum::Json;
-async fn all_posts(Extension(db) : Extension<sqlx::SqlitePool>) -> Json<Vec<BlogPost>> {
-    let posts = sqlx::query_as::<_, BlogPost>("SELECT * FROM blog_posts")
-        .fetch_all(&db)
-        .await
-        .expect("Unable to fetch posts");
-
-    Json(posts)
-}
-
-// Return a single blog post by ID number
-use axum::extract::Path;
-async fn get_post(Extension(db) : Extension<sqlx::SqlitePool>, Path(id) : Path<i32>) -> Json<BlogPost> {
-    let post = sqlx::query_as::<_, BlogPost>("SELECT * FROM blog_posts WHERE id = ?")
-        .bind(id)
-        .fetch_one(&db)
+    // Read the settings from the config file, environment and command line.
+    // The .env file counts as part of the environment.
+    dotenv::dotenv().ok();
+    let config = Config::load();
+    config.init_logging();
+    let database_url = config
+        .database_url
+        .as_deref()
+        .expect("You've not set the database URL: use database_url, DATABASE_URL or --database-url");
+
+    // Connect to the database and run any migrations that haven't been applied
+    let store = SqliteStore::connect(database_url, config.pool_size)
         .await
-        .expect("Unable to fetch post");
-    Json(post)
+        .expect("Unable to open the database");
+    let store = Arc::new(store);
+
+    // All of the routes live in `blog_api`, shared with `blog_server`, as
+    // does setting up the rate limits and logins from the config
+    let app = blog_api::router_from_config(store.clone(), &config).expect("Unable to set up the server");
+
+    // Serve on every configured address (by default, localhost port 3001)
+    // until we're asked to stop, then close the database before exiting
+    let drain_timeout = Duration::from_secs(config.shutdown_timeout);
+    let served = blog_api::serve(app, &config.listen, drain_timeout).await;
+    if let Err(e) = store.close().await {
+        tracing::error!("Unable to close the database: {e}");
+        std::process::exit(1);
+    }
+    if let Err(message) = served {
+        tracing::error!("{message}");
+        std::process::exit(1);
+    }
 }
-
-// Add a blog entry
-async fn new_post(Extension(db) : Extension<sqlx::SqlitePool>, Json(post) : Json<BlogPost>) -> Json<i32> {
-    use sqlx::Row;
-    const SQL: &str = "INSERT INTO blog_posts (date, title, body, author) VALUES (?, ?, ?, ?) RETURNING id";
-    let new_id = sqlx::query(SQL)
-        .bind(post.date)
-        .bind(post.title)
-        .bind(post.body)
-        .bind(post.author)
-        .fetch_one(&db)
-        .await
-        .expect("Unable to insert post")
-        .get::<i32, _>("id");
-
-    Json(new_id)
-}
\ No newline at end of file
@@ -0,0 +1,679 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Mutex;
+use std::task::{ready, Context, Poll};
+use std::time::Instant;
+
+use async_trait::async_trait;
+use blog_api::{
+    snippet_to_html, ApiError, Histogram, ListQuery, MetricsText, PostStore, SearchQuery, SearchTerm, SortField,
+    SortOrder, StoreVersion, MARK_END, MARK_START,
+};
+use blog_model::{BlogPost, Comment, NewComment, NewPost, PostPage, PostPatch, SearchHit, TagCount};
+use chrono::{DateTime, SecondsFormat, Utc};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::{ConnectOptions, FromRow, QueryBuilder, Sqlite, SqliteConnection, SqlitePool};
+use tracing::instrument::Instrumented;
+use tracing::Instrument;
+
+/// Keeps blog posts in the `blog_posts` table of an SQLite database.
+pub struct SqliteStore {
+    db: SqlitePool,
+}
+
+impl SqliteStore {
+    /// Connect to the database with a pool of up to `pool_size` connections,
+    /// running any migrations that haven't been applied yet.
+    pub async fn connect(database_url: &str, pool_size: u32) -> Result<Self, sqlx::Error> {
+        // Statements are logged by `timed` instead
+        let options = database_url.parse::<SqliteConnectOptions>()?.disable_statement_logging();
+        let db = SqlitePoolOptions::new().max_connections(pool_size).connect_with(options).await?;
+        sqlx::migrate!().run(&db).await?;
+        Ok(Self { db })
+    }
+}
+
+// Every post query selects the post's tags too, as one string separated by
+// ASCII unit separators.
+const TAGS_COLUMN: &str = "(SELECT group_concat(tags.name, char(31))
+        FROM post_tags JOIN tags ON tags.id = post_tags.tag_id
+        WHERE post_tags.post_id = blog_posts.id) AS tags";
+const TAG_SEPARATOR: char = '\u{1f}';
+
+// A row of `blog_posts`, as SQLite stores it. The text columns are nullable,
+// and the date is text.
+#[derive(FromRow)]
+struct PostRow {
+    id: i32,
+    date: String,
+    title: Option<String>,
+    body: Option<String>,
+    author: Option<String>,
+    tags: Option<String>,
+    version: i64,
+}
+
+impl TryFrom<PostRow> for BlogPost {
+    type Error = ApiError;
+
+    fn try_from(row: PostRow) -> Result<Self, Self::Error> {
+        Ok(BlogPost {
+            id: row.id,
+            date: parse_date(&row.date)?,
+            title: row.title.unwrap_or_default(),
+            body: row.body.unwrap_or_default(),
+            author: row.author.unwrap_or_default(),
+            tags: {
+                let mut tags: Vec<String> = row
+                    .tags
+                    .as_deref()
+                    .unwrap_or_default()
+                    .split(TAG_SEPARATOR)
+                    .filter(|tag| !tag.is_empty())
+                    .map(|tag| tag.to_string())
+                    .collect();
+                tags.sort();
+                tags
+            },
+            version: row.version,
+        })
+    }
+}
+
+// A row of `comments`
+#[derive(FromRow)]
+struct CommentRow {
+    id: i32,
+    post_id: i32,
+    parent_id: Option<i32>,
+    date: String,
+    author: String,
+    body: String,
+    deleted: bool,
+}
+
+impl TryFrom<CommentRow> for Comment {
+    type Error = ApiError;
+
+    fn try_from(row: CommentRow) -> Result<Self, Self::Error> {
+        Ok(Comment {
+            id: row.id,
+            post_id: row.post_id,
+            parent_id: row.parent_id,
+            date: parse_date(&row.date)?,
+            author: row.author,
+            body: row.body,
+            deleted: row.deleted,
+        })
+    }
+}
+
+// A post found by a full-text search
+#[derive(FromRow)]
+struct SearchRow {
+    #[sqlx(flatten)]
+    post: PostRow,
+    score: f64,
+    snippet: String,
+}
+
+// Dates are stored in a fixed-width format so that they sort as text.
+fn format_date(date: &DateTime<Utc>) -> String {
+    date.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+fn parse_date(date: &str) -> Result<DateTime<Utc>, ApiError> {
+    DateTime::parse_from_rfc3339(date)
+        .map(|date| date.with_timezone(&Utc))
+        .map_err(|e| ApiError::Database(format!("Invalid date {date:?} in blog_posts: {e}")))
+}
+
+// The SQL expression each sort field orders by. Titles may be NULL, which
+// would break the keyset comparison, so they sort as empty strings.
+fn sort_column(sort: SortField) -> &'static str {
+    match sort {
+        SortField::Id => "id",
+        SortField::Date => "date",
+        SortField::Title => "COALESCE(title, '')",
+    }
+}
+
+// Turn a parsed query into FTS5's query syntax. Every word is quoted, so
+// nothing the user types can be mistaken for an FTS5 operator.
+fn fts_query(query: &SearchQuery) -> String {
+    let quote = |word: &str| format!("\"{}\"", word.replace('"', "\"\""));
+    query
+        .terms
+        .iter()
+        .map(|term| match term {
+            SearchTerm::Word(word) => quote(word),
+            SearchTerm::Prefix(prefix) => format!("{} *", quote(prefix)),
+            SearchTerm::Phrase(words) => quote(&words.join(" ")),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Replace a post's tags, creating any tags that don't exist yet
+async fn set_tags(db: &mut SqliteConnection, post_id: i32, tags: &[String]) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM post_tags WHERE post_id = ?")
+        .bind(post_id)
+        .execute(&mut *db)
+        .timed("delete post tags")
+        .await?;
+    for tag in tags {
+        sqlx::query("INSERT INTO tags (name) VALUES (?) ON CONFLICT (name) DO NOTHING")
+            .bind(tag)
+            .execute(&mut *db)
+            .timed("insert tag")
+            .await?;
+        sqlx::query("INSERT INTO post_tags (post_id, tag_id) SELECT ?, id FROM tags WHERE name = ?")
+            .bind(post_id)
+            .bind(tag)
+            .execute(&mut *db)
+            .timed("insert post tag")
+            .await?;
+    }
+    Ok(())
+}
+
+// Forget tags that no post uses any more
+async fn prune_tags(db: &mut SqliteConnection) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM tags WHERE id NOT IN (SELECT tag_id FROM post_tags)")
+        .execute(db)
+        .timed("prune tags")
+        .await?;
+    Ok(())
+}
+
+async fn post_exists(db: &mut SqliteConnection, post_id: i32) -> Result<bool, sqlx::Error> {
+    sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM blog_posts WHERE id = ?)")
+        .bind(post_id)
+        .fetch_one(db)
+        .timed("post exists")
+        .await
+}
+
+// An update or delete that changed nothing had either the wrong ID or the
+// wrong version. `Ok` means there's no such post.
+async fn missing_or_conflict(db: &mut SqliteConnection, id: i32) -> Result<(), ApiError> {
+    if post_exists(db, id).await.map_err(ApiError::database)? {
+        Err(ApiError::PreconditionFailed)
+    } else {
+        Ok(())
+    }
+}
+
+// How long each statement has taken, for /metrics. It's shared by every
+// store, but there's only ever one.
+static QUERY_LATENCY: Mutex<BTreeMap<&'static str, Histogram>> = Mutex::new(BTreeMap::new());
+
+// Lets a statement be run with `.timed("what it does")`: in a span of its
+// own, which is logged with how long it took when it closes (at debug
+// level), and timed for /metrics. sqlx's own statement logging is no use, as
+// it runs SQLite statements on a worker thread, outside any request's span.
+trait Timed: Future + Sized {
+    fn timed(self, query: &'static str) -> TimedQuery<Self> {
+        TimedQuery {
+            query,
+            started: Instant::now(),
+            // Boxed so that there's no pinning to worry about
+            statement: Box::pin(self).instrument(tracing::debug_span!("sql", query)),
+        }
+    }
+}
+
+impl<F: Future> Timed for F {}
+
+struct TimedQuery<F> {
+    query: &'static str,
+    started: Instant,
+    statement: Instrumented<Pin<Box<F>>>,
+}
+
+impl<F: Future> Future for TimedQuery<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let output = ready!(Pin::new(&mut self.statement).poll(cx));
+        let mut latency = QUERY_LATENCY.lock().unwrap();
+        latency.entry(self.query).or_default().observe(self.started.elapsed());
+        Poll::Ready(output)
+    }
+}
+
+fn to_posts(rows: Vec<PostRow>) -> Result<Vec<BlogPost>, ApiError> {
+    rows.into_iter().map(BlogPost::try_from).collect()
+}
+
+// Each operation gets a span, so the statements it runs can be traced back to
+// it, and to the request it was for
+#[async_trait]
+impl PostStore for SqliteStore {
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn list(&self, query: &ListQuery) -> Result<PostPage, ApiError> {
+        let mut sql = QueryBuilder::<Sqlite>::new(format!("SELECT blog_posts.*, {TAGS_COLUMN} FROM blog_posts WHERE 1 = 1"));
+        if let Some(author) = &query.author {
+            sql.push(" AND author = ").push_bind(author.clone()).push(" COLLATE NOCASE");
+        }
+        if let Some(tag) = &query.tag {
+            sql.push(
+                " AND EXISTS (SELECT 1 FROM post_tags JOIN tags ON tags.id = post_tags.tag_id
+                    WHERE post_tags.post_id = blog_posts.id AND tags.name = ",
+            )
+            .push_bind(tag.clone())
+            .push(")");
+        }
+        if let Some(since) = &query.since {
+            sql.push(" AND date >= ").push_bind(format_date(since));
+        }
+        if let Some(until) = &query.until {
+            sql.push(" AND date < ").push_bind(format_date(until));
+        }
+
+        // Keyset pagination: carry on from the (sort key, id) in the cursor
+        let column = sort_column(query.sort);
+        let (direction, comparison) = match query.order {
+            SortOrder::Asc => ("ASC", ">"),
+            SortOrder::Desc => ("DESC", "<"),
+        };
+        if let Some(cursor) = &query.after {
+            let key = match query.sort {
+                SortField::Id => None,
+                SortField::Date => Some(cursor.date.as_ref().map(format_date)),
+                SortField::Title => Some(cursor.title.clone()),
+            };
+            match key {
+                None => {
+                    sql.push(format!(" AND id {comparison} ")).push_bind(cursor.id);
+                }
+                Some(Some(key)) => {
+                    sql.push(format!(" AND ({column} {comparison} "))
+                        .push_bind(key.clone())
+                        .push(format!(" OR ({column} = "))
+                        .push_bind(key)
+                        .push(format!(" AND id {comparison} "))
+                        .push_bind(cursor.id)
+                        .push("))");
+                }
+                // A cursor without its key sorts after every post, as in
+                // `ListQuery::is_after_cursor`
+                Some(None) => {
+                    if query.order == SortOrder::Asc {
+                        sql.push(" AND 0");
+                    }
+                }
+            }
+        }
+
+        // Fetch one extra row to find out whether there's another page
+        sql.push(format!(" ORDER BY {column} {direction}, id {direction} LIMIT "))
+            .push_bind(query.limit as i64 + 1);
+
+        let mut posts = to_posts(
+            sql.build_query_as::<PostRow>()
+                .fetch_all(&self.db)
+                .timed("list posts")
+                .await
+                .map_err(ApiError::database)?,
+        )?;
+        let next_cursor = if posts.len() > query.limit {
+            posts.truncate(query.limit);
+            posts.last().map(|post| query.cursor_after(post))
+        } else {
+            None
+        };
+        Ok(PostPage { posts, next_cursor })
+    }
+
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn get(&self, id: i32) -> Result<Option<BlogPost>, ApiError> {
+        let sql = format!("SELECT blog_posts.*, {TAGS_COLUMN} FROM blog_posts WHERE id = ?");
+        let row = sqlx::query_as::<_, PostRow>(&sql)
+            .bind(id)
+            .fetch_optional(&self.db)
+            .timed("get post")
+            .await
+            .map_err(ApiError::database)?;
+        row.map(BlogPost::try_from).transpose()
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn create(&self, post: NewPost) -> Result<BlogPost, ApiError> {
+        const SQL: &str = "INSERT INTO blog_posts (date, title, body, author) VALUES (?, ?, ?, ?) RETURNING id";
+        let mut tx = self.db.begin().await.map_err(ApiError::database)?;
+        let id: i32 = sqlx::query_scalar(SQL)
+            .bind(format_date(&Utc::now()))
+            .bind(post.title)
+            .bind(post.body)
+            .bind(post.author)
+            .fetch_one(&mut *tx)
+            .timed("insert post")
+            .await
+            .map_err(ApiError::database)?;
+        set_tags(&mut tx, id, &post.tags).await.map_err(ApiError::database)?;
+        tx.commit().await.map_err(ApiError::database)?;
+
+        self.get(id)
+            .await?
+            .ok_or_else(|| ApiError::Database(format!("Post {id} vanished after it was created")))
+    }
+
+    #[tracing::instrument(level = "debug", skip(self, patch))]
+    async fn update(&self, id: i32, patch: PostPatch, expected_version: i64) -> Result<Option<BlogPost>, ApiError> {
+        // COALESCE keeps the current value when the parameter is NULL
+        const SQL: &str = "UPDATE blog_posts SET
+            date = COALESCE(?, date),
+            title = COALESCE(?, title),
+            body = COALESCE(?, body),
+            author = COALESCE(?, author),
+            version = version + 1
+            WHERE id = ? AND version = ?";
+        let mut tx = self.db.begin().await.map_err(ApiError::database)?;
+        let result = sqlx::query(SQL)
+            .bind(patch.date.as_ref().map(format_date))
+            .bind(patch.title)
+            .bind(patch.body)
+            .bind(patch.author)
+            .bind(id)
+            .bind(expected_version)
+            .execute(&mut *tx)
+            .timed("update post")
+            .await
+            .map_err(ApiError::database)?;
+        if result.rows_affected() == 0 {
+            return missing_or_conflict(&mut tx, id).await.map(|()| None);
+        }
+        if let Some(tags) = &patch.tags {
+            set_tags(&mut tx, id, tags).await.map_err(ApiError::database)?;
+            prune_tags(&mut tx).await.map_err(ApiError::database)?;
+        }
+        tx.commit().await.map_err(ApiError::database)?;
+
+        self.get(id).await
+    }
+
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn delete(&self, id: i32, expected_version: i64) -> Result<bool, ApiError> {
+        // The post's tags go with it, thanks to ON DELETE CASCADE
+        let mut tx = self.db.begin().await.map_err(ApiError::database)?;
+        let result = sqlx::query("DELETE FROM blog_posts WHERE id = ? AND version = ?")
+            .bind(id)
+            .bind(expected_version)
+            .execute(&mut *tx)
+            .timed("delete post")
+            .await
+            .map_err(ApiError::database)?;
+        if result.rows_affected() == 0 {
+            return missing_or_conflict(&mut tx, id).await.map(|()| false);
+        }
+        prune_tags(&mut tx).await.map_err(ApiError::database)?;
+        tx.commit().await.map_err(ApiError::database)?;
+        Ok(true)
+    }
+
+    #[tracing::instrument(level = "debug", skip(self, query))]
+    async fn search(&self, query: &SearchQuery, limit: usize) -> Result<Vec<SearchHit>, ApiError> {
+        // bm25() is smaller for better matches, and a title match counts ten
+        // times as much as a body match. The snippet comes from whichever
+        // column matched best.
+        let sql = format!(
+            "SELECT blog_posts.*, {TAGS_COLUMN},
+                -bm25(blog_posts_fts, 10.0, 1.0) AS score,
+                snippet(blog_posts_fts, -1, ?, ?, '…', 16) AS snippet
+            FROM blog_posts_fts
+            JOIN blog_posts ON blog_posts.id = blog_posts_fts.rowid
+            WHERE blog_posts_fts MATCH ?
+            ORDER BY bm25(blog_posts_fts, 10.0, 1.0)
+            LIMIT ?"
+        );
+        let rows = sqlx::query_as::<_, SearchRow>(&sql)
+            .bind(MARK_START.to_string())
+            .bind(MARK_END.to_string())
+            .bind(fts_query(query))
+            .bind(limit as i64)
+            .fetch_all(&self.db)
+            .timed("search posts")
+            .await
+            .map_err(ApiError::database)?;
+        rows.into_iter()
+            .map(|row| {
+                Ok(SearchHit {
+                    post: row.post.try_into()?,
+                    score: row.score,
+                    snippet: snippet_to_html(&row.snippet),
+                })
+            })
+            .collect()
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn tags(&self) -> Result<Vec<TagCount>, ApiError> {
+        const SQL: &str = "SELECT tags.name, COUNT(*) FROM tags
+            JOIN post_tags ON post_tags.tag_id = tags.id
+            GROUP BY tags.id
+            ORDER BY tags.name";
+        let rows: Vec<(String, i64)> = sqlx::query_as(SQL)
+            .fetch_all(&self.db)
+            .timed("count tags")
+            .await
+            .map_err(ApiError::database)?;
+        Ok(rows.into_iter().map(|(tag, posts)| TagCount { tag, posts }).collect())
+    }
+
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn comments(&self, post_id: i32) -> Result<Option<Vec<Comment>>, ApiError> {
+        let mut db = self.db.acquire().await.map_err(ApiError::database)?;
+        if !post_exists(&mut db, post_id).await.map_err(ApiError::database)? {
+            return Ok(None);
+        }
+        let rows = sqlx::query_as::<_, CommentRow>("SELECT * FROM comments WHERE post_id = ? ORDER BY id")
+            .bind(post_id)
+            .fetch_all(&mut *db)
+            .timed("list comments")
+            .await
+            .map_err(ApiError::database)?;
+        rows.into_iter().map(Comment::try_from).collect::<Result<_, _>>().map(Some)
+    }
+
+    #[tracing::instrument(level = "debug", skip(self, comment))]
+    async fn add_comment(&self, post_id: i32, comment: NewComment) -> Result<Option<Comment>, ApiError> {
+        let mut tx = self.db.begin().await.map_err(ApiError::database)?;
+        if !post_exists(&mut tx, post_id).await.map_err(ApiError::database)? {
+            return Ok(None);
+        }
+        if let Some(parent_id) = comment.parent_id {
+            let parent_ok: bool =
+                sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM comments WHERE id = ? AND post_id = ? AND deleted = 0)")
+                    .bind(parent_id)
+                    .bind(post_id)
+                    .fetch_one(&mut *tx)
+                    .timed("parent exists")
+                    .await
+                    .map_err(ApiError::database)?;
+            if !parent_ok {
+                return Err(ApiError::BadRequest(format!("There is no comment {parent_id} on this post to reply to")));
+            }
+        }
+
+        const SQL: &str = "INSERT INTO comments (post_id, parent_id, date, author, body) VALUES (?, ?, ?, ?, ?) RETURNING *";
+        let row = sqlx::query_as::<_, CommentRow>(SQL)
+            .bind(post_id)
+            .bind(comment.parent_id)
+            .bind(format_date(&Utc::now()))
+            .bind(comment.author)
+            .bind(comment.body)
+            .fetch_one(&mut *tx)
+            .timed("insert comment")
+            .await
+            .map_err(ApiError::database)?;
+        tx.commit().await.map_err(ApiError::database)?;
+        row.try_into().map(Some)
+    }
+
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn delete_comment(&self, post_id: i32, comment_id: i32) -> Result<bool, ApiError> {
+        let mut tx = self.db.begin().await.map_err(ApiError::database)?;
+        let parent_id: Option<Option<i32>> = sqlx::query_scalar("SELECT parent_id FROM comments WHERE id = ? AND post_id = ?")
+            .bind(comment_id)
+            .bind(post_id)
+            .fetch_optional(&mut *tx)
+            .timed("get comment")
+            .await
+            .map_err(ApiError::database)?;
+        let Some(mut parent_id) = parent_id else {
+            return Ok(false);
+        };
+
+        // Keep a tombstone if anyone has replied
+        const TOMBSTONE: &str = "UPDATE comments SET deleted = 1, author = '', body = ''
+            WHERE id = ? AND EXISTS (SELECT 1 FROM comments AS replies WHERE replies.parent_id = comments.id)";
+        let tombstoned = sqlx::query(TOMBSTONE)
+            .bind(comment_id)
+            .execute(&mut *tx)
+            .timed("tombstone comment")
+            .await
+            .map_err(ApiError::database)?
+            .rows_affected()
+            > 0;
+
+        // Otherwise remove it, and any tombstones that only existed for it
+        if !tombstoned {
+            sqlx::query("DELETE FROM comments WHERE id = ?")
+                .bind(comment_id)
+                .execute(&mut *tx)
+                .timed("delete comment")
+                .await
+                .map_err(ApiError::database)?;
+            const PRUNE: &str = "DELETE FROM comments
+                WHERE id = ? AND deleted = 1
+                AND NOT EXISTS (SELECT 1 FROM comments AS replies WHERE replies.parent_id = comments.id)
+                RETURNING parent_id";
+            while let Some(id) = parent_id {
+                let removed: Option<Option<i32>> = sqlx::query_scalar(PRUNE)
+                    .bind(id)
+                    .fetch_optional(&mut *tx)
+                    .timed("prune tombstone")
+                    .await
+                    .map_err(ApiError::database)?;
+                match removed {
+                    Some(next) => parent_id = next,
+                    None => break,
+                }
+            }
+        }
+
+        tx.commit().await.map_err(ApiError::database)?;
+        Ok(true)
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn version(&self) -> Result<StoreVersion, ApiError> {
+        // Kept up to date by triggers; see the store_meta migration
+        let (version, modified): (i64, String) = sqlx::query_as("SELECT version, modified FROM store_meta")
+            .fetch_one(&self.db)
+            .timed("store version")
+            .await
+            .map_err(ApiError::database)?;
+        Ok(StoreVersion {
+            version,
+            modified: parse_date(&modified)?,
+        })
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn write_metrics(&self, out: &mut MetricsText) -> Result<(), ApiError> {
+        const SQL: &str = "SELECT (SELECT COUNT(*) FROM blog_posts), (SELECT COUNT(*) FROM comments WHERE deleted = 0)";
+        let (posts, comments): (i64, i64) = sqlx::query_as(SQL)
+            .fetch_one(&self.db)
+            .timed("count posts")
+            .await
+            .map_err(ApiError::database)?;
+        out.gauge("blog_posts", "Posts in the store.", posts);
+        out.gauge("blog_comments", "Comments in the store, not counting deleted ones.", comments);
+
+        let open = self.db.size();
+        let idle = self.db.num_idle() as u32;
+        out.metric("blog_db_connections", "gauge", "Open database connections, by whether they're in use.");
+        out.sample("blog_db_connections", &[("state", "active")], open.saturating_sub(idle));
+        out.sample("blog_db_connections", &[("state", "idle")], idle);
+        let max = self.db.options().get_max_connections();
+        out.gauge("blog_db_max_connections", "The most database connections the pool will open.", max);
+
+        out.metric("blog_db_query_duration_seconds", "histogram", "How long SQL statements took, by what they do.");
+        for (query, histogram) in QUERY_LATENCY.lock().unwrap().iter() {
+            out.histogram("blog_db_query_duration_seconds", &[("query", query)], histogram);
+        }
+        Ok(())
+    }
+
+    #[tracing::instrument(level = "debug", skip_all)]
+    async fn close(&self) -> Result<(), ApiError> {
+        // Waits for connections in use to be returned, then closes them all
+        self.db.close().await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use blog_api::store_tests;
+
+    use super::*;
+
+    // A new database of its own, without the posts the migrations start it
+    // with. An in-memory database only lasts as long as its connection, so
+    // there's just the one.
+    async fn empty_store() -> SqliteStore {
+        let store = SqliteStore::connect("sqlite::memory:", 1).await.unwrap();
+        sqlx::query("DELETE FROM blog_posts").execute(&store.db).await.unwrap();
+        store
+    }
+
+    #[tokio::test]
+    async fn migrations_leave_readable_posts() {
+        let store = SqliteStore::connect("sqlite::memory:", 1).await.unwrap();
+        let page = store.list(&ListQuery::default()).await.unwrap();
+        let posts: Vec<_> = page.posts.iter().map(|post| (post.title.as_str(), post.date.to_rfc3339())).collect();
+        assert_eq!(
+            posts,
+            [
+                ("A Tale of Two Cities", "2021-01-01T00:00:00+00:00".to_string()),
+                ("Moby Dick", "2021-01-02T00:00:00+00:00".to_string()),
+            ]
+        );
+        let whale = SearchQuery::parse("ishmael").unwrap();
+        assert_eq!(store.search(&whale, 10).await.unwrap()[0].post.title, "Moby Dick");
+    }
+
+    #[tokio::test]
+    async fn lists() {
+        store_tests::lists(&empty_store().await).await;
+    }
+
+    #[tokio::test]
+    async fn keyless_cursors_sort_last() {
+        store_tests::keyless_cursors_sort_last(&empty_store().await).await;
+    }
+
+    #[tokio::test]
+    async fn searches() {
+        store_tests::searches(&empty_store().await).await;
+    }
+
+    #[tokio::test]
+    async fn tags() {
+        store_tests::tags(&empty_store().await).await;
+    }
+
+    #[tokio::test]
+    async fn comments() {
+        store_tests::comments(&empty_store().await).await;
+    }
+
+    #[tokio::test]
+    async fn versions() {
+        store_tests::versions(&empty_store().await).await;
+    }
+}
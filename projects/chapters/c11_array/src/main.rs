@@ -1,17 +1,6 @@
-#[derive(Debug)]
-struct User {
-    username: String,
-    password: String,    
-}
-
-impl User {
-    fn new(username: &str, password: &str) -> User {
-        User {
-            username: username.to_string(),
-            password: password.to_string(),
-        }
-    }
-}
+// A user, with their password kept only as a hash. It's shared by the
+// chapters that need users, so they all store passwords the same way.
+use passwords::User;
 
 fn get_users() -> [User; 2] {
     [
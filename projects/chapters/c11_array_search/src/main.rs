@@ -1,17 +1,4 @@
-#[derive(Debug)]
-struct User {
-    username: String,
-    password: String,    
-}
-
-impl User {
-    fn new(username: &str, password: &str) -> User {
-        User {
-            username: username.to_string(),
-            password: password.to_string(),
-        }
-    }
-}
+use passwords::User;
 
 fn get_users() -> [User; 2] {
     [
@@ -20,10 +7,12 @@ fn get_users() -> [User; 2] {
     ]
 }
 
+// Logging in checks the password against its hash, and needs the user to be
+// mutable: if the hash is out of date, it's replaced.
 fn is_login_valid(username: &str, password: &str) -> bool {
     let users = get_users();
-    for user in users {
-        if user.username == username && user.password == password {
+    for mut user in users {
+        if user.username == username && user.log_in(password) {
             return true;
         }
     }
@@ -31,8 +20,8 @@ fn is_login_valid(username: &str, password: &str) -> bool {
 }
 
 fn is_login_valid_iter(username: &str, password: &str) -> bool {
-    get_users().iter()
-        .find(|user| user.username == username && user.password == password).is_some()
+    get_users().iter_mut()
+        .any(|user| user.username == username && user.log_in(password))
 }
 
 fn main() {
@@ -1,26 +1,13 @@
 use std::collections::HashMap;
-
-#[derive(Debug)]
-struct User {
-    username: String,
-    password: String,    
-}
-
-impl User {
-    fn new(username: &str, password: &str) -> User {
-        User {
-            username: username.to_string(),
-            password: password.to_string(),
-        }
-    }
-}
+use passwords::User;
 
 fn main() {
     let mut users = HashMap::new();
     users.insert("admin", User::new("admin", "password"));
     users.insert("herbert", User::new("herbert", "password"));
     
-    if let Some(user) = users.get("herbert") {
+    if let Some(user) = users.get_mut("herbert") {
         println!("Found user: {:#?}", user);
+        println!("Is login valid? {}", user.log_in("password"));
     }
 }
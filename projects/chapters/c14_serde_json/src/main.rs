@@ -1,19 +1,6 @@
-use serde::{Serialize, Deserialize};
-
-#[derive(Debug, Serialize, Deserialize)]
-struct User {
-    username: String,
-    password: String,    
-}
-
-impl User {
-    fn new(username: &str, password: &str) -> User {
-        User {
-            username: username.to_string(),
-            password: password.to_string(),
-        }
-    }
-}
+// `User` derives Serialize and Deserialize when the `passwords` crate's
+// "serde" feature is on
+use passwords::User;
 
 fn main() {
     let user = User::new("herbert", "password");
@@ -38,4 +25,11 @@ fn main() {
     println!("Serialized:\n {}", serialized);
     let deserialized: User = serde_yaml::from_str(&serialized).unwrap();
     println!("Deserialized: {:#?}", deserialized);
+
+    // Users saved before passwords were hashed have them in plain text.
+    // Logging in replaces it with a hash, ready to be saved again.
+    println!("\nUpgrading an old user");
+    let mut old: User = serde_json::from_str(r#"{"username":"herbert","password":"password"}"#).unwrap();
+    println!("Is login valid? {}", old.log_in("password"));
+    println!("Saved again:\n {}", serde_json::to_string(&old).unwrap());
 }
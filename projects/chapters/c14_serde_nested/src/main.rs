@@ -5,16 +5,16 @@ enum LoginAction { Admin, User, Denied }
 
 #[derive(Debug, Serialize, Deserialize)]
 struct User {
-    username: String,
-    password: String,
+    // The username and password hash, written alongside login_action
+    #[serde(flatten)]
+    login: passwords::User,
     login_action: LoginAction,
 }
 
 impl User {
     fn new(username: &str, password: &str, login_action: LoginAction) -> User {
         User {
-            username: username.to_string(),
-            password: password.to_string(),
+            login: passwords::User::new(username, password),
             login_action,
         }
     }
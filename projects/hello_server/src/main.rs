@@ -1,22 +1,29 @@
 use axum::{routing::get, Router};
-use std::net::SocketAddr;
+use server_config::HelloConfig;
 
 #[tokio::main]
 async fn main() {
+    // Read the settings from the config file, environment and command line
+    let config = HelloConfig::load();
+    config.init_logging();
+
     // Bind the default route to the function `say_hello_text`
     let app = Router::new().route("/", get(say_hello_text));
 
-    // Listen on localhost, port 3000
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3001));
-
-    // Start the server
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
+    // Start a server on every configured address (by default, localhost
+    // port 3001), and run until one of them fails
+    let mut servers = tokio::task::JoinSet::new();
+    for addr in &config.listen {
+        let server = axum::Server::try_bind(addr).unwrap_or_else(|e| panic!("Unable to listen on {addr}: {e}"));
+        servers.spawn(server.serve(app.clone().into_make_service()));
+        tracing::info!("Listening on http://{addr}");
+    }
+    while let Some(result) = servers.join_next().await {
+        result.expect("The server panicked").expect("The server failed");
+    }
 }
 
 // Return a static string
 async fn say_hello_text() -> &'static str {
     "Hello, world!"
-}
\ No newline at end of file
+}
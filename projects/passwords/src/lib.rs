@@ -0,0 +1,169 @@
+//! Password hashing for anything with users: Argon2id hashes in the PHC
+//! string format (`$argon2id$v=19$m=...`), checked in constant time, with a
+//! way to tell when a stored password should be hashed again. `User` puts
+//! it all together for anything that just needs people to log in.
+
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher as _, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Version};
+use subtle::ConstantTimeEq;
+
+mod user;
+
+pub use argon2::Params;
+pub use user::User;
+
+/// How a password compared with what was stored for it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verified {
+    No,
+    Yes,
+    /// The password is right, but isn't stored the way it should be: it's
+    /// plaintext, or was hashed with other parameters. Store a fresh
+    /// `PasswordHasher::hash` of it.
+    NeedsRehash,
+}
+
+impl Verified {
+    /// Was the password right, however it was stored?
+    pub fn is_valid(self) -> bool {
+        self != Verified::No
+    }
+}
+
+/// A stored password that's in the PHC string format, but isn't an Argon2
+/// hash that can be read. Nothing verifies against it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnreadableHash;
+
+impl std::fmt::Display for UnreadableHash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("the password hash isn't an Argon2 hash that can be read")
+    }
+}
+
+impl std::error::Error for UnreadableHash {}
+
+/// Hashes passwords with Argon2id and checks them against stored hashes.
+#[derive(Clone)]
+pub struct PasswordHasher {
+    argon2: Argon2<'static>,
+}
+
+impl Default for PasswordHasher {
+    /// A hasher using Argon2id's default parameters.
+    fn default() -> Self {
+        Self::new(Params::default())
+    }
+}
+
+impl PasswordHasher {
+    /// A hasher using Argon2id and `params`. Hashes made with other
+    /// parameters still verify, but as `Verified::NeedsRehash`.
+    pub fn new(params: Params) -> Self {
+        Self {
+            argon2: Argon2::new(Algorithm::Argon2id, Version::V0x13, params),
+        }
+    }
+
+    /// A hash of `password` to store, with a random salt.
+    pub fn hash(&self, password: &str) -> String {
+        let salt = SaltString::generate(&mut OsRng);
+        self.argon2
+            .hash_password(password.as_bytes(), &salt)
+            .expect("Argon2 hashes any password")
+            .to_string()
+    }
+
+    /// Check `password` against what was `stored` for it. Anything that
+    /// isn't a PHC string (`$<scheme>$...`) is a plaintext password from
+    /// before they were hashed; a PHC string that isn't Argon2 is an error.
+    pub fn verify(&self, stored: &str, password: &str) -> Result<Verified, UnreadableHash> {
+        if !is_phc_string(stored) {
+            return Ok(match bool::from(stored.as_bytes().ct_eq(password.as_bytes())) {
+                true => Verified::NeedsRehash,
+                false => Verified::No,
+            });
+        }
+
+        let hash = PasswordHash::new(stored).map_err(|_| UnreadableHash)?;
+        let params = Params::try_from(&hash).map_err(|_| UnreadableHash)?;
+        if Algorithm::try_from(hash.algorithm).is_err() || hash.hash.is_none() {
+            return Err(UnreadableHash);
+        }
+        // This compares the hashes in constant time
+        if self.argon2.verify_password(password.as_bytes(), &hash).is_err() {
+            return Ok(Verified::No);
+        }
+        Ok(match self.is_current(&hash, &params) {
+            true => Verified::Yes,
+            false => Verified::NeedsRehash,
+        })
+    }
+
+    // Was the hash made the way we'd make it now?
+    fn is_current(&self, hash: &PasswordHash, params: &Params) -> bool {
+        let current = self.argon2.params();
+        hash.algorithm == Algorithm::Argon2id.ident()
+            && hash.version == Some(Version::V0x13.into())
+            && params.m_cost() == current.m_cost()
+            && params.t_cost() == current.t_cost()
+            && params.p_cost() == current.p_cost()
+            && params.output_len() == Some(current.output_len().unwrap_or(Params::DEFAULT_OUTPUT_LEN))
+    }
+}
+
+// `$<scheme>$<rest>`, which a plaintext password could only be by accident
+fn is_phc_string(stored: &str) -> bool {
+    stored
+        .strip_prefix('$')
+        .and_then(|rest| rest.split_once('$'))
+        .is_some_and(|(scheme, _)| !scheme.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Cheap parameters, so the tests don't spend their time hashing
+    fn hasher() -> PasswordHasher {
+        PasswordHasher::new(Params::new(8, 1, 1, None).unwrap())
+    }
+
+    #[test]
+    fn hashes_verify() {
+        let hasher = hasher();
+        let hash = hasher.hash("hunter2");
+        assert!(hash.starts_with("$argon2id$v=19$"));
+        assert_eq!(hasher.verify(&hash, "hunter2"), Ok(Verified::Yes));
+        assert_eq!(hasher.verify(&hash, "hunter3"), Ok(Verified::No));
+        assert_ne!(hasher.hash("hunter2"), hash, "salts should differ");
+    }
+
+    #[test]
+    fn plaintext_needs_rehashing() {
+        let hasher = hasher();
+        assert_eq!(hasher.verify("password", "password"), Ok(Verified::NeedsRehash));
+        assert_eq!(hasher.verify("password", "Password"), Ok(Verified::No));
+        // Only a PHC string counts as a hash
+        assert_eq!(hasher.verify("$password", "$password"), Ok(Verified::NeedsRehash));
+        assert_eq!(hasher.verify("$$x", "$$x"), Ok(Verified::NeedsRehash));
+    }
+
+    #[test]
+    fn other_parameters_need_rehashing() {
+        let old = PasswordHasher::new(Params::new(16, 1, 1, None).unwrap()).hash("hunter2");
+        assert_eq!(hasher().verify(&old, "hunter2"), Ok(Verified::NeedsRehash));
+        assert_eq!(hasher().verify(&old, "hunter3"), Ok(Verified::No));
+    }
+
+    #[test]
+    fn other_schemes_are_rejected() {
+        let hasher = hasher();
+        let bcrypt = "$2b$12$R9h/cIPz0gi.URNNX3kh2OPST9/PgBkqquzi.Ss7KIUgO2t0jWMUW";
+        assert_eq!(hasher.verify(bcrypt, bcrypt), Err(UnreadableHash));
+        let scrypt = "$scrypt$ln=16,r=8,p=1$aM15713r3Xsvxbi31lqr1Q$nFNh2CVHVjNldFVKDHDlm4CbdRSCdEBsjjJxD+iCs5E";
+        assert_eq!(hasher.verify(scrypt, "password"), Err(UnreadableHash));
+        assert_eq!(hasher.verify("$argon2id$nonsense", "password"), Err(UnreadableHash));
+    }
+}
@@ -0,0 +1,102 @@
+use crate::{PasswordHasher, Verified};
+
+/// Someone who logs in with a password. Only a hash of the password is kept,
+/// and it's brought up to date whenever they log in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct User {
+    pub username: String,
+    /// An Argon2id hash of the user's password, in the PHC string format.
+    /// Anything that isn't a PHC string is taken to be a plain password from
+    /// before they were hashed, and is replaced by a hash the first time the
+    /// user logs in.
+    pub password: String,
+}
+
+impl User {
+    /// A user whose password is hashed with Argon2id's default parameters.
+    pub fn new(username: &str, password: &str) -> Self {
+        Self::with_hasher(&PasswordHasher::default(), username, password)
+    }
+
+    /// A user whose password is hashed by `hasher`.
+    pub fn with_hasher(hasher: &PasswordHasher, username: &str, password: &str) -> Self {
+        Self {
+            username: username.to_string(),
+            password: hasher.hash(password),
+        }
+    }
+
+    /// Check the user's password, with Argon2id's default parameters. See
+    /// `log_in_with`.
+    pub fn log_in(&mut self, password: &str) -> bool {
+        self.log_in_with(&PasswordHasher::default(), password)
+    }
+
+    /// Check the user's password. If it's right but isn't stored the way
+    /// `hasher` would store it now - it's plaintext, or was hashed with other
+    /// parameters - it's hashed again, so save the user afterwards.
+    pub fn log_in_with(&mut self, hasher: &PasswordHasher, password: &str) -> bool {
+        match hasher.verify(&self.password, password) {
+            Ok(Verified::Yes) => true,
+            Ok(Verified::NeedsRehash) => {
+                self.password = hasher.hash(password);
+                true
+            }
+            Ok(Verified::No) | Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Params;
+
+    fn hasher(m_cost: u32) -> PasswordHasher {
+        PasswordHasher::new(Params::new(m_cost, 1, 1, None).unwrap())
+    }
+
+    #[test]
+    fn passwords_are_only_kept_hashed() {
+        let mut user = User::with_hasher(&hasher(8), "herbert", "hunter2");
+        assert!(user.password.starts_with("$argon2id$"));
+        let hash = user.password.clone();
+        assert!(user.log_in_with(&hasher(8), "hunter2"));
+        assert!(!user.log_in_with(&hasher(8), "hunter3"));
+        assert_eq!(user.password, hash, "a current hash is left alone");
+    }
+
+    #[test]
+    fn plaintext_is_upgraded_on_the_first_login() {
+        let mut user = User {
+            username: "herbert".to_string(),
+            password: "hunter2".to_string(),
+        };
+        assert!(!user.log_in_with(&hasher(8), "hunter3"));
+        assert_eq!(user.password, "hunter2", "a wrong password changes nothing");
+
+        assert!(user.log_in_with(&hasher(8), "hunter2"));
+        assert!(user.password.starts_with("$argon2id$"));
+        assert_eq!(hasher(8).verify(&user.password, "hunter2"), Ok(Verified::Yes));
+    }
+
+    #[test]
+    fn old_hashes_are_redone_with_the_current_parameters() {
+        let mut user = User::with_hasher(&hasher(16), "herbert", "hunter2");
+        assert!(user.log_in_with(&hasher(8), "hunter2"));
+        assert!(user.password.contains("m=8,"), "{}", user.password);
+        assert_eq!(hasher(8).verify(&user.password, "hunter2"), Ok(Verified::Yes));
+    }
+
+    #[test]
+    fn unknown_schemes_never_log_in() {
+        let bcrypt = "$2b$12$R9h/cIPz0gi.URNNX3kh2OPST9/PgBkqquzi.Ss7KIUgO2t0jWMUW";
+        let mut user = User {
+            username: "herbert".to_string(),
+            password: bcrypt.to_string(),
+        };
+        assert!(!user.log_in_with(&hasher(8), bcrypt));
+        assert_eq!(user.password, bcrypt);
+    }
+}
@@ -0,0 +1,612 @@
+//! Settings for the servers: `Config` for `blog_server` and `blog_server_db`,
+//! and the few of them that `hello_server` needs in `HelloConfig`.
+//!
+//! Every setting has a default, which can be overridden by a TOML file (named
+//! with `--config` or `BLOG_CONFIG`), which can be overridden by an
+//! environment variable, which can be overridden by a command-line flag. Run
+//! a server with `--help` for the flags and variables, or `--print-config` to
+//! see the settings it would use.
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use clap::{Args, Parser, ValueEnum};
+use serde::{Deserialize, Serialize};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::format::FmtSpan;
+
+/// The longest, in seconds, that `token_lifetime` or any of the `login_*`
+/// settings can be: a year, which keeps the times they're added to well
+/// within range.
+pub const MAX_LOGIN_SECONDS: u64 = 365 * 24 * 60 * 60;
+
+/// How much the servers log.
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+/// How log lines are written.
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One human-readable line per event.
+    #[default]
+    Text,
+    /// One JSON object per line, for log collectors.
+    Json,
+}
+
+/// What `blog_server` does if its write-ahead log ends in a half-written
+/// entry, as a crash can leave it.
+#[derive(Serialize, Deserialize, ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum WalRecovery {
+    /// Refuse to start.
+    #[default]
+    Strict,
+    /// Drop the damaged entry and start anyway.
+    Tolerant,
+}
+
+/// Everything a blog server can be configured with. Each server ignores the
+/// settings that don't apply to it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Where to listen for HTTP connections.
+    pub listen: Vec<SocketAddr>,
+    /// `blog_server_db`'s database, e.g. `sqlite:blog.db`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub database_url: Option<String>,
+    /// The most database connections to hold open at once.
+    pub pool_size: u32,
+    /// The largest request body accepted, in bytes.
+    pub body_limit: usize,
+    /// How many reads (GETs) each client may make per second, on average.
+    /// Zero turns the limit off.
+    pub read_rate: f64,
+    /// How many reads a client may make in a burst, above `read_rate`.
+    pub read_burst: u32,
+    /// How many writes (anything but a GET) each client may make per second,
+    /// on average. Zero turns the limit off.
+    pub write_rate: f64,
+    /// How many writes a client may make in a burst, above `write_rate`.
+    pub write_burst: u32,
+    /// A JSON file listing the users who can log in and make changes.
+    /// Without one, nobody can. The server writes upgraded password hashes
+    /// back to it, so it should be writable.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub users_file: Option<PathBuf>,
+    /// The secret that login tokens are signed with. Without one, a random
+    /// secret is used, so tokens stop working when the server restarts. It's
+    /// left out of `--print-config`.
+    #[serde(skip_serializing)]
+    pub token_secret: Option<String>,
+    /// How long a login token lasts, in seconds.
+    pub token_lifetime: u64,
+    /// How many failed logins, for one username or from one address, lock
+    /// it out. Zero turns lockouts off.
+    pub login_max_failures: u32,
+    /// How long, in seconds, failed logins count towards a lockout.
+    pub login_failure_window: u64,
+    /// How long the first lockout lasts, in seconds. Each lockout after it
+    /// lasts twice as long as the one before, up to `login_max_lockout`.
+    pub login_lockout: u64,
+    /// The longest a lockout lasts, in seconds.
+    pub login_max_lockout: u64,
+    pub log_level: LogLevel,
+    pub log_format: LogFormat,
+    /// How long to wait, in seconds, for requests in progress to finish when
+    /// the server is asked to stop.
+    pub shutdown_timeout: u64,
+    /// Where `blog_server` saves its posts. Without it, they're lost when the
+    /// server stops.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_dir: Option<PathBuf>,
+    pub wal_recovery: WalRecovery,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen: vec![SocketAddr::from(([127, 0, 0, 1], 3001))],
+            database_url: None,
+            pool_size: 5,
+            body_limit: 2 * 1024 * 1024,
+            read_rate: 20.0,
+            read_burst: 100,
+            write_rate: 1.0,
+            write_burst: 10,
+            users_file: None,
+            token_secret: None,
+            token_lifetime: 60 * 60,
+            login_max_failures: 5,
+            login_failure_window: 15 * 60,
+            login_lockout: 60,
+            login_max_lockout: 60 * 60,
+            log_level: LogLevel::default(),
+            log_format: LogFormat::default(),
+            shutdown_timeout: 30,
+            data_dir: None,
+            wal_recovery: WalRecovery::default(),
+        }
+    }
+}
+
+// The settings on the command line that every server has. Every flag can
+// also be set with an environment variable, and clap prefers the flag when
+// there are both.
+#[derive(Args)]
+struct SharedFlags {
+    /// Read settings from this TOML file
+    #[arg(long, env = "BLOG_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Address to listen on, e.g. 127.0.0.1:3001. Repeat the flag, or separate
+    /// addresses with commas, to listen on more than one
+    #[arg(long, env = "BLOG_LISTEN", value_delimiter = ',')]
+    listen: Vec<SocketAddr>,
+
+    /// How much to log
+    #[arg(long, env = "BLOG_LOG_LEVEL")]
+    log_level: Option<LogLevel>,
+
+    /// How to write log lines
+    #[arg(long, env = "BLOG_LOG_FORMAT")]
+    log_format: Option<LogFormat>,
+
+    /// Print the settings that would be used, as TOML, and exit
+    #[arg(long)]
+    print_config: bool,
+}
+
+// The blog servers' command line
+#[derive(Parser)]
+#[command(about = "A blog server. Settings come from flags, then environment variables, then the config file.")]
+struct Flags {
+    #[command(flatten)]
+    shared: SharedFlags,
+
+    /// Database URL, e.g. sqlite:blog.db
+    #[arg(long, env = "DATABASE_URL")]
+    database_url: Option<String>,
+
+    /// Most database connections to hold open at once
+    #[arg(long, env = "BLOG_POOL_SIZE")]
+    pool_size: Option<u32>,
+
+    /// Largest request body accepted, in bytes
+    #[arg(long, env = "BLOG_BODY_LIMIT")]
+    body_limit: Option<usize>,
+
+    /// Reads each client may make per second (0 for no limit)
+    #[arg(long, env = "BLOG_READ_RATE")]
+    read_rate: Option<f64>,
+
+    /// Reads each client may make in a burst
+    #[arg(long, env = "BLOG_READ_BURST")]
+    read_burst: Option<u32>,
+
+    /// Writes each client may make per second (0 for no limit)
+    #[arg(long, env = "BLOG_WRITE_RATE")]
+    write_rate: Option<f64>,
+
+    /// Writes each client may make in a burst
+    #[arg(long, env = "BLOG_WRITE_BURST")]
+    write_burst: Option<u32>,
+
+    /// JSON file of the users who can log in
+    #[arg(long, env = "BLOG_USERS_FILE")]
+    users_file: Option<PathBuf>,
+
+    /// Secret to sign login tokens with
+    #[arg(long, env = "BLOG_TOKEN_SECRET", hide_env_values = true)]
+    token_secret: Option<String>,
+
+    /// Seconds a login token lasts
+    #[arg(long, env = "BLOG_TOKEN_LIFETIME")]
+    token_lifetime: Option<u64>,
+
+    /// Failed logins that lock out a username or address (0 for no lockouts)
+    #[arg(long, env = "BLOG_LOGIN_MAX_FAILURES")]
+    login_max_failures: Option<u32>,
+
+    /// Seconds that failed logins count towards a lockout
+    #[arg(long, env = "BLOG_LOGIN_FAILURE_WINDOW")]
+    login_failure_window: Option<u64>,
+
+    /// Seconds the first lockout lasts; each one after lasts twice as long
+    #[arg(long, env = "BLOG_LOGIN_LOCKOUT")]
+    login_lockout: Option<u64>,
+
+    /// Seconds the longest lockout lasts
+    #[arg(long, env = "BLOG_LOGIN_MAX_LOCKOUT")]
+    login_max_lockout: Option<u64>,
+
+    /// Seconds to wait for requests in progress to finish when stopping
+    #[arg(long, env = "BLOG_SHUTDOWN_TIMEOUT")]
+    shutdown_timeout: Option<u64>,
+
+    /// Directory to save posts in (blog_server only)
+    #[arg(long, env = "BLOG_DATA_DIR")]
+    data_dir: Option<PathBuf>,
+
+    /// What to do if the write-ahead log ends in a damaged entry
+    #[arg(long, env = "BLOG_WAL_RECOVERY")]
+    wal_recovery: Option<WalRecovery>,
+}
+
+// `hello_server`'s command line
+#[derive(Parser)]
+#[command(about = "A server that says hello. Settings come from flags, then environment variables, then the config file.")]
+struct HelloFlags {
+    #[command(flatten)]
+    shared: SharedFlags,
+}
+
+impl Config {
+    /// Work out the settings from the defaults, the config file, the
+    /// environment and the command line. If they're invalid this prints why
+    /// and exits, as it does after printing them for `--print-config`.
+    pub fn load() -> Self {
+        let flags = Flags::parse();
+        let print_config = flags.shared.print_config;
+        load_or_exit(Self::from_flags(flags), print_config)
+    }
+
+    /// Send log events at `log_level` and above to stderr, written as
+    /// `log_format` says. Spans are logged as they close, with how long they
+    /// took, so each request gets a line. Call this once, early in `main`.
+    pub fn init_logging(&self) {
+        init_logging(self.log_level, self.log_format);
+    }
+
+    /// Read settings from a TOML file. Anything the file leaves out keeps its
+    /// default.
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {e}", path.display()))?;
+        toml::from_str(&text).map_err(|e| format!("Invalid settings in {}: {e}", path.display()))
+    }
+
+    fn from_flags(flags: Flags) -> Result<Self, String> {
+        let mut config = match &flags.shared.config {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+
+        if !flags.shared.listen.is_empty() {
+            config.listen = flags.shared.listen;
+        }
+        if flags.database_url.is_some() {
+            config.database_url = flags.database_url;
+        }
+        config.pool_size = flags.pool_size.unwrap_or(config.pool_size);
+        config.body_limit = flags.body_limit.unwrap_or(config.body_limit);
+        config.read_rate = flags.read_rate.unwrap_or(config.read_rate);
+        config.read_burst = flags.read_burst.unwrap_or(config.read_burst);
+        config.write_rate = flags.write_rate.unwrap_or(config.write_rate);
+        config.write_burst = flags.write_burst.unwrap_or(config.write_burst);
+        if flags.users_file.is_some() {
+            config.users_file = flags.users_file;
+        }
+        if flags.token_secret.is_some() {
+            config.token_secret = flags.token_secret;
+        }
+        config.token_lifetime = flags.token_lifetime.unwrap_or(config.token_lifetime);
+        config.login_max_failures = flags.login_max_failures.unwrap_or(config.login_max_failures);
+        config.login_failure_window = flags.login_failure_window.unwrap_or(config.login_failure_window);
+        config.login_lockout = flags.login_lockout.unwrap_or(config.login_lockout);
+        config.login_max_lockout = flags.login_max_lockout.unwrap_or(config.login_max_lockout);
+        config.log_level = flags.shared.log_level.unwrap_or(config.log_level);
+        config.log_format = flags.shared.log_format.unwrap_or(config.log_format);
+        config.shutdown_timeout = flags.shutdown_timeout.unwrap_or(config.shutdown_timeout);
+        if flags.data_dir.is_some() {
+            config.data_dir = flags.data_dir;
+        }
+        config.wal_recovery = flags.wal_recovery.unwrap_or(config.wal_recovery);
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.listen.is_empty() {
+            return Err("There must be at least one address to listen on".to_string());
+        }
+        if self.pool_size == 0 {
+            return Err("pool_size must be at least 1".to_string());
+        }
+        if self.body_limit == 0 {
+            return Err("body_limit must be at least 1 byte".to_string());
+        }
+        for (name, rate, burst) in [("read", self.read_rate, self.read_burst), ("write", self.write_rate, self.write_burst)] {
+            if !(rate.is_finite() && rate >= 0.0) {
+                return Err(format!("{name}_rate must be a number of requests per second, or 0 for no limit"));
+            }
+            if rate > 0.0 && burst == 0 {
+                return Err(format!("{name}_burst must be at least 1 while there's a {name}_rate"));
+            }
+        }
+        if self.token_secret.as_deref().is_some_and(|secret| secret.len() < 32) {
+            return Err("token_secret must be at least 32 characters".to_string());
+        }
+        if self.token_lifetime == 0 {
+            return Err("token_lifetime must be at least 1 second".to_string());
+        }
+        if self.login_max_failures > 0 {
+            if self.login_failure_window == 0 || self.login_lockout == 0 {
+                return Err("login_failure_window and login_lockout must be at least 1 second".to_string());
+            }
+            if self.login_max_lockout < self.login_lockout {
+                return Err("login_max_lockout can't be shorter than login_lockout".to_string());
+            }
+        }
+        for (name, seconds) in [
+            ("token_lifetime", self.token_lifetime),
+            ("login_failure_window", self.login_failure_window),
+            ("login_lockout", self.login_lockout),
+            ("login_max_lockout", self.login_max_lockout),
+        ] {
+            if seconds > MAX_LOGIN_SECONDS {
+                return Err(format!("{name} can't be longer than {MAX_LOGIN_SECONDS} seconds (a year)"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What `hello_server` can be configured with: where to listen, and how to
+/// log. It reads the same config file and environment variables as the blog
+/// servers, and ignores the settings that are only for them.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct HelloConfig {
+    /// Where to listen for HTTP connections.
+    pub listen: Vec<SocketAddr>,
+    pub log_level: LogLevel,
+    pub log_format: LogFormat,
+}
+
+impl Default for HelloConfig {
+    fn default() -> Self {
+        let config = Config::default();
+        Self {
+            listen: config.listen,
+            log_level: config.log_level,
+            log_format: config.log_format,
+        }
+    }
+}
+
+impl HelloConfig {
+    /// Work out the settings the way `Config::load` does.
+    pub fn load() -> Self {
+        let flags = HelloFlags::parse();
+        let print_config = flags.shared.print_config;
+        load_or_exit(Self::from_flags(flags), print_config)
+    }
+
+    /// Set up logging the way `Config::init_logging` does.
+    pub fn init_logging(&self) {
+        init_logging(self.log_level, self.log_format);
+    }
+
+    /// Read settings from a TOML file. Anything the file leaves out keeps its
+    /// default, and settings only for the blog servers are ignored.
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {e}", path.display()))?;
+        toml::from_str(&text).map_err(|e| format!("Invalid settings in {}: {e}", path.display()))
+    }
+
+    fn from_flags(flags: HelloFlags) -> Result<Self, String> {
+        let mut config = match &flags.shared.config {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+
+        if !flags.shared.listen.is_empty() {
+            config.listen = flags.shared.listen;
+        }
+        config.log_level = flags.shared.log_level.unwrap_or(config.log_level);
+        config.log_format = flags.shared.log_format.unwrap_or(config.log_format);
+
+        if config.listen.is_empty() {
+            return Err("There must be at least one address to listen on".to_string());
+        }
+        Ok(config)
+    }
+}
+
+// The settings `from_flags` worked out, or exit saying why they're invalid.
+// Printing them for `--print-config` exits too.
+fn load_or_exit<T: Serialize>(config: Result<T, String>, print_config: bool) -> T {
+    let config = match config {
+        Ok(config) => config,
+        Err(message) => {
+            eprintln!("error: {message}");
+            std::process::exit(2);
+        }
+    };
+
+    if print_config {
+        print!("{}", to_toml(&config));
+        std::process::exit(0);
+    }
+    config
+}
+
+// Settings as `--print-config` prints them
+fn to_toml<T: Serialize>(config: &T) -> String {
+    toml::to_string_pretty(config).expect("Settings always serialize")
+}
+
+fn init_logging(log_level: LogLevel, log_format: LogFormat) {
+    let level = match log_level {
+        LogLevel::Error => LevelFilter::ERROR,
+        LogLevel::Warn => LevelFilter::WARN,
+        LogLevel::Info => LevelFilter::INFO,
+        LogLevel::Debug => LevelFilter::DEBUG,
+        LogLevel::Trace => LevelFilter::TRACE,
+    };
+    let logger = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(std::io::stderr);
+    match log_format {
+        LogFormat::Text => logger.init(),
+        // Include the fields of the span an event happened in, and those
+        // around it, so every line carries its request's ID
+        LogFormat::Json => logger.json().with_current_span(true).with_span_list(true).init(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use std::sync::{Mutex, MutexGuard, PoisonError};
+
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    // Environment variables are shared by every test thread, so only one
+    // test uses them at a time, and they're removed again when it's done
+    static ENV: Mutex<()> = Mutex::new(());
+
+    struct ScopedEnv {
+        names: Vec<&'static str>,
+        _lock: MutexGuard<'static, ()>,
+    }
+
+    fn scoped_env(vars: &[(&'static str, &str)]) -> ScopedEnv {
+        let lock = ENV.lock().unwrap_or_else(PoisonError::into_inner);
+        for (name, value) in vars {
+            std::env::set_var(name, value);
+        }
+        ScopedEnv {
+            names: vars.iter().map(|(name, _)| *name).collect(),
+            _lock: lock,
+        }
+    }
+
+    impl Drop for ScopedEnv {
+        fn drop(&mut self) {
+            for name in &self.names {
+                std::env::remove_var(name);
+            }
+        }
+    }
+
+    fn settings_file(toml: &str) -> NamedTempFile {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(toml.as_bytes()).unwrap();
+        file
+    }
+
+    // The settings for a blog server started with `args`, and whatever's in
+    // the environment
+    fn config(args: &[&str]) -> Config {
+        Config::from_flags(Flags::try_parse_from(["blog_server"].iter().chain(args)).unwrap()).unwrap()
+    }
+
+    const FILE: &str = "read_rate = 5.0\nwrite_rate = 5.0\nlog_format = \"json\"\n";
+
+    #[test]
+    fn the_file_overrides_the_defaults() {
+        let file = settings_file(FILE);
+        let _env = scoped_env(&[]);
+        let config = config(&["--config", file.path().to_str().unwrap()]);
+        assert_eq!((config.read_rate, config.write_rate, config.log_format), (5.0, 5.0, LogFormat::Json));
+        assert_eq!(config.read_burst, Config::default().read_burst);
+    }
+
+    #[test]
+    fn the_environment_overrides_the_file() {
+        let file = settings_file(FILE);
+        let _env = scoped_env(&[("BLOG_CONFIG", file.path().to_str().unwrap()), ("BLOG_READ_RATE", "7")]);
+        let config = config(&[]);
+        assert_eq!((config.read_rate, config.write_rate, config.log_format), (7.0, 5.0, LogFormat::Json));
+    }
+
+    #[test]
+    fn flags_override_the_environment() {
+        let file = settings_file(FILE);
+        let _env = scoped_env(&[("BLOG_READ_RATE", "7"), ("BLOG_LOG_FORMAT", "text")]);
+        let config = config(&["--config", file.path().to_str().unwrap(), "--read-rate", "9"]);
+        assert_eq!((config.read_rate, config.write_rate, config.log_format), (9.0, 5.0, LogFormat::Text));
+    }
+
+    #[test]
+    fn print_config_prints_the_merged_settings() {
+        let file = settings_file(FILE);
+        let _env = scoped_env(&[("BLOG_READ_RATE", "7")]);
+        let args = ["blog_server", "--config", file.path().to_str().unwrap(), "--write-rate", "9", "--print-config"];
+        let flags = Flags::try_parse_from(args).unwrap();
+        assert!(flags.shared.print_config);
+
+        let printed = to_toml(&Config::from_flags(flags).unwrap());
+        for line in ["read_rate = 7.0", "write_rate = 9.0", "log_format = \"json\"", "read_burst = 100"] {
+            assert!(printed.lines().any(|printed| printed == line), "{line} in\n{printed}");
+        }
+        let merged = Config {
+            read_rate: 7.0,
+            write_rate: 9.0,
+            log_format: LogFormat::Json,
+            ..Config::default()
+        };
+        assert_eq!(toml::from_str::<Config>(&printed), Ok(merged));
+    }
+
+    #[test]
+    fn the_defaults_are_valid() {
+        assert_eq!(Config::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn hello_config_reads_the_blog_settings() {
+        let blog = Config {
+            listen: vec![SocketAddr::from(([0, 0, 0, 0], 8080))],
+            log_format: LogFormat::Json,
+            data_dir: Some(PathBuf::from("/var/lib/blog")),
+            ..Config::default()
+        };
+        let hello: HelloConfig = toml::from_str(&toml::to_string(&blog).unwrap()).unwrap();
+        assert_eq!(
+            hello,
+            HelloConfig {
+                listen: blog.listen,
+                log_level: LogLevel::Info,
+                log_format: LogFormat::Json,
+            }
+        );
+        assert_eq!(toml::from_str::<HelloConfig>("").unwrap(), HelloConfig::default());
+    }
+
+    #[test]
+    fn login_durations_are_bounded() {
+        let config = Config {
+            token_lifetime: MAX_LOGIN_SECONDS,
+            login_lockout: MAX_LOGIN_SECONDS,
+            login_max_lockout: MAX_LOGIN_SECONDS,
+            login_failure_window: MAX_LOGIN_SECONDS,
+            ..Config::default()
+        };
+        assert_eq!(config.validate(), Ok(()));
+
+        for config in [
+            Config { token_lifetime: MAX_LOGIN_SECONDS + 1, ..config.clone() },
+            Config { login_failure_window: MAX_LOGIN_SECONDS + 1, ..config.clone() },
+            Config { login_max_lockout: u64::MAX, ..config.clone() },
+            Config { login_lockout: u64::MAX, login_max_lockout: u64::MAX, ..config.clone() },
+        ] {
+            assert!(config.validate().unwrap_err().contains("can't be longer than"));
+        }
+    }
+}